@@ -1,39 +1,31 @@
+use crate::api::bf1api::models::{
+    AuthCodeParams, EnvIdViaAuthCode, PlayerStats, RpcAck, RpcResponse,
+};
+use crate::api::bf1api::request::{RateLimiter, RequestPolicy};
+use crate::api::bf1api::roster::{RosterFailover, RosterPolicy};
 use crate::api::endpoints;
 use crate::api::errors::{ApiResultExt, BF1ApiError, BF1ApiSubError};
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue, COOKIE};
 use reqwest::redirect::Policy;
 use reqwest::{cookie, Client, StatusCode};
-use serde::ser::SerializeSeq;
-use serde::{Deserialize, Serialize, Serializer};
-use serde_json::{Map, Value};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env;
 use std::str::FromStr;
+use std::time::Instant;
+use tokio::sync::Mutex;
 use uuid::Uuid;
 
+mod ban;
 mod kick;
+pub mod models;
 mod persona;
+pub mod request;
+pub mod roster;
 pub mod server;
-
-trait Lookup {
-    fn lookup(&self, search_term: &str) -> Result<&Value, BF1ApiSubError>;
-}
-
-impl Lookup for Map<String, Value> {
-    fn lookup(&self, search_term: &str) -> Result<&Value, BF1ApiSubError> {
-        self.get(search_term)
-            .ok_or(BF1ApiSubError::JsonError(format!(
-                "Didn't find '{}' field",
-                search_term
-            )))
-    }
-}
-
-impl Lookup for Value {
-    fn lookup(&self, search_term: &str) -> Result<&Value, BF1ApiSubError> {
-        self.as_object().unwrap().lookup(search_term)
-    }
-}
+mod stats;
+mod vip;
 
 #[derive(Deserialize, Debug)]
 struct AccessToken {
@@ -65,9 +57,11 @@ async fn get_auth_code(
         let location = response
             .headers()
             .get("location")
-            .unwrap()
+            .ok_or(BF1ApiSubError::ResponseError(
+                "Auth code response had no location header".to_string(),
+            ))?
             .to_str()
-            .unwrap();
+            .map_err(|err| BF1ApiSubError::ResponseError(err.to_string()))?;
         if location.contains("127.0.0.1/success?code=") {
             let cookies: Vec<cookie::Cookie> = response.cookies().collect();
             if cookies.len() == 2 {
@@ -86,18 +80,14 @@ async fn get_auth_code(
 }
 
 #[derive(Serialize, Debug)]
-struct RPC {
+struct RPC<P: Serialize> {
     id: String,
     jsonrpc: String,
     method: String,
-    params: HashMap<String, Value>,
+    params: P,
 }
 
-pub fn rpc_request(method: String, params: HashMap<&str, Value>) -> RPC {
-    let params = params
-        .into_iter()
-        .map(|(k, v)| (k.to_string(), v))
-        .collect();
+pub fn rpc_request<P: Serialize>(method: String, params: P) -> RPC<P> {
     RPC {
         id: Uuid::new_v4().to_string(),
         jsonrpc: "2.0".to_string(),
@@ -106,44 +96,40 @@ pub fn rpc_request(method: String, params: HashMap<&str, Value>) -> RPC {
     }
 }
 
-async fn get_session_and_persona_ids_by_authcode(
+async fn send_rpc<P: Serialize, T: DeserializeOwned>(
     client: &Client,
-    auth_code: &str,
-) -> Result<(String, String), BF1ApiError> {
-    let params: HashMap<&str, Value> = HashMap::from([
-        ("authCode", Value::String(auth_code.to_string())),
-        ("locale", Value::String("en-GB".to_string())),
-    ]);
-    let body = rpc_request("Authentication.getEnvIdViaAuthCode".to_string(), params);
+    rpc_header: &HeaderMap,
+    method: &str,
+    params: P,
+) -> Result<RpcResponse<T>, BF1ApiError> {
+    let body = rpc_request(method.to_string(), params);
     let response = client
         .post(endpoints::RPC_HOST)
+        .headers(rpc_header.clone())
         .json(&body)
         .send()
         .await?
         .error_for_status()?;
 
-    let mut response_json: Map<String, Value> =
-        serde_json::from_str(response.text().await?.as_str())?;
-
-    let result = response_json.lookup("result")?.as_object().unwrap();
-
-    let session_id = result
-        .lookup("sessionId")?
-        .as_str()
-        .ok_or(BF1ApiSubError::JsonError(
-            "Couldn't parse 'sessionId' field as string".to_string(),
-        ))?
-        .to_string();
-
-    let persona_id = result
-        .lookup("personaId")?
-        .as_str()
-        .ok_or(BF1ApiSubError::JsonError(
-            "Couldn't parse 'personaId' field as string".to_string(),
-        ))?
-        .to_string();
+    Ok(serde_json::from_str(response.text().await?.as_str())?)
+}
 
-    Ok((session_id, persona_id))
+async fn get_session_and_persona_ids_by_authcode(
+    client: &Client,
+    auth_code: &str,
+) -> Result<EnvIdViaAuthCode, BF1ApiError> {
+    let params = AuthCodeParams {
+        auth_code: auth_code.to_string(),
+        locale: "en-GB".to_string(),
+    };
+    send_rpc::<_, EnvIdViaAuthCode>(
+        client,
+        &HeaderMap::new(),
+        "Authentication.getEnvIdViaAuthCode",
+        params,
+    )
+    .await?
+    .into_result()
 }
 
 async fn get_access_token(
@@ -167,11 +153,18 @@ pub struct BF1Api {
     access_token: AccessToken,
     rpc_header: HeaderMap,
     session_id: String,
-    persona_id: String,
+    persona_id: u64,
+    policy: RequestPolicy,
+    rate_limiter: RateLimiter,
+    roster: RosterFailover,
+    stats_cache: Mutex<HashMap<u64, (Instant, PlayerStats)>>,
 }
 
 impl BF1Api {
-    pub async fn new() -> Result<BF1Api, BF1ApiError> {
+    pub async fn new(
+        policy: RequestPolicy,
+        roster_policy: RosterPolicy,
+    ) -> Result<BF1Api, BF1ApiError> {
         if let None = dotenv::dotenv().ok() {
             return Err(BF1ApiSubError::EnvError(String::from("No .env file found")).into());
         }
@@ -204,8 +197,12 @@ impl BF1Api {
         println!("Resp Auth Remid: {}", resp_auth.remid);
         println!("Resp Auth Sid: {}", resp_auth.sid);
         println!("Resp Auth Code: {}", resp_auth.code);
-        let (session_id, persona_id) =
-            get_session_and_persona_ids_by_authcode(&client, resp_auth.code.as_str()).await?;
+        let EnvIdViaAuthCode {
+            session_id,
+            persona_id,
+        } = get_session_and_persona_ids_by_authcode(&client, resp_auth.code.as_str())
+            .await
+            .provide_api_function("Get Session ID")?;
 
         println!("Session ID: {}, Persona ID: {}", session_id, persona_id);
 
@@ -222,10 +219,40 @@ impl BF1Api {
             rpc_header,
             session_id,
             persona_id,
+            rate_limiter: RateLimiter::new(&policy),
+            policy,
+            roster: RosterFailover::new(roster_policy),
+            stats_cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn persona_id(&self) -> u64 {
+        self.persona_id
+    }
+
+    // Read only lookups, retried on transient failures
+    async fn rpc<P: Serialize, T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<T, BF1ApiError> {
+        self.with_retry(endpoints::RPC_HOST, || async {
+            send_rpc::<&P, T>(&self.client, &self.rpc_header, method, &params)
+                .await?
+                .into_result()
         })
+        .await
+        .provide_api_function(method)
     }
 
-    pub fn persona_id(&self) -> String {
-        self.persona_id.clone()
+    // For actions where we only care that the call went through, never retried here
+    async fn rpc_ack<P: Serialize>(&self, method: &str, params: P) -> Result<(), BF1ApiError> {
+        self.once(
+            endpoints::RPC_HOST,
+            send_rpc::<P, _>(&self.client, &self.rpc_header, method, params),
+        )
+        .await
+        .and_then(|response: RpcAck| response.into_ack())
+        .provide_api_function(method)
     }
 }
@@ -0,0 +1,43 @@
+use crate::api::bf1api::models::{Persona, ServerPersonaParams, GAME};
+use crate::api::bf1api::BF1Api;
+use crate::api::errors::BF1ApiError;
+
+impl BF1Api {
+    pub async fn get_server_id(&self, game_id: &str) -> Result<u64, BF1ApiError> {
+        Ok(self
+            .get_full_server_details(game_id)
+            .await?
+            .rsp_info()?
+            .server
+            .server_id)
+    }
+
+    pub async fn ban_player(&self, server_id: u64, persona_id: u64) -> Result<(), BF1ApiError> {
+        let params = ServerPersonaParams {
+            game: GAME,
+            server_id: server_id.to_string(),
+            persona_id: persona_id.to_string(),
+        };
+
+        self.rpc_ack("RSP.addServerBan", params).await
+    }
+
+    pub async fn unban_player(&self, server_id: u64, persona_id: u64) -> Result<(), BF1ApiError> {
+        let params = ServerPersonaParams {
+            game: GAME,
+            server_id: server_id.to_string(),
+            persona_id: persona_id.to_string(),
+        };
+
+        self.rpc_ack("RSP.removeServerBan", params).await
+    }
+
+    pub async fn get_ban_list(&self, game_id: &str) -> Result<Vec<Persona>, BF1ApiError> {
+        Ok(self
+            .get_full_server_details(game_id)
+            .await?
+            .rsp_info()?
+            .banned_list
+            .clone())
+    }
+}
@@ -1,78 +1,359 @@
-use crate::api::bf1api::{rpc_request, BF1Api};
-use crate::api::endpoints;
-use crate::api::errors::{BF1ApiError, BF1ApiSubError};
-use crate::config::{add_to_player_kick_record, Config, PlayerKickHistoryRecord};
-use crate::console::update_kick_count;
-use crate::discord::{announce_kick_fail, announce_kick_success, DiscordWebhook};
+use crate::api::bf1api::models::{GametoolsPlayers, KickPlayerParams, GAME};
+use crate::api::bf1api::BF1Api;
+use crate::api::errors::BF1ApiError;
+use crate::config::{
+    add_temp_ban, add_to_player_kick_record, add_to_shadow_history, rule_name,
+    take_expired_temp_bans, Config, EscalationAction, KickVerification, ServerConfig, TempBan,
+};
+use crate::console::{log, log_info, update_kick_count};
+use crate::discord::{
+    announce_ban_fail, announce_ban_success, announce_kick_fail, announce_kick_success,
+    announce_kick_unconfirmed, announce_player_warning, announce_shadow_kick, DiscordWebhook,
+};
 use crate::errors::KickbotError;
+use crate::history::{HistoryStore, KickAction, KickContext, KickEntry};
 use crate::recognition::model::WeaponClasses;
 use crate::BotStats;
-use serde_json::Value;
-use std::collections::HashMap;
-use std::ops::DerefMut;
+use chrono::Utc;
+use std::ops::Deref;
 use std::sync::{Arc, OnceLock};
 use tokio::sync::{Mutex, RwLock};
+use tokio::time::sleep;
 
 impl BF1Api {
+    async fn send_kick(
+        &self,
+        game_id: String,
+        persona_id: u64,
+        reason: String,
+    ) -> Result<(), BF1ApiError> {
+        let params = KickPlayerParams {
+            game: GAME,
+            game_id,
+            persona_id: persona_id.to_string(),
+            reason,
+        };
+        let mut attempt = 0;
+        loop {
+            match self.rpc_ack("RSP.kickPlayer", &params).await {
+                Err(err) if err.is_transient() && attempt < self.policy.max_retries => {
+                    // The kick may have gone through even though the request failed,
+                    // only try again if they're still on the server
+                    match self
+                        .get_roster(&params.game_id, &GametoolsPlayers::single(persona_id))
+                        .await
+                    {
+                        Ok(players) if !players.contains(persona_id) => return Ok(()),
+                        Ok(_) => {
+                            sleep(self.policy.backoff(attempt)).await;
+                            attempt += 1;
+                        }
+                        Err(_) => return Err(err),
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn ban_player_by_game_id(
+        &self,
+        game_id: String,
+        persona_id: u64,
+        reason: String,
+    ) -> Result<u64, BF1ApiError> {
+        let server_id = self.get_server_id(&game_id).await?;
+        self.ban_player(server_id, persona_id).await?;
+
+        // Bans don't remove them from the current round
+        if let Err(err) = self.send_kick(game_id, persona_id, reason).await {
+            log(&err);
+        }
+        Ok(server_id)
+    }
+
+    // Unbans anyone whose temp ban on server_id has run out, called from the refresh loop
+    pub async fn lift_expired_temp_bans(&self, server_id: u64) {
+        let expired = match take_expired_temp_bans(server_id) {
+            Ok(expired) => expired,
+            Err(err) => {
+                log(&err);
+                return;
+            }
+        };
+        for temp_ban in expired {
+            match self.unban_player(server_id, temp_ban.persona_id).await {
+                Ok(_) => log_info(
+                    format!("Temp ban for {} has run out, unbanned", temp_ban.persona_id).as_str(),
+                ),
+                Err(err) => {
+                    log(&err);
+                    // Try again next refresh
+                    if let Err(err) = add_temp_ban(temp_ban) {
+                        log(&err);
+                    }
+                }
+            }
+        }
+    }
+
+    // Watches the roster until the player leaves, kicking again between rounds of checks if
+    // retries are set. False if they're still there after every retry
+    async fn verify_kick(
+        &self,
+        game_id: &str,
+        persona_id: u64,
+        reason: &str,
+        verification: &KickVerification,
+    ) -> bool {
+        for retry in 0..=verification.retries {
+            if retry > 0 {
+                if let Err(err) = self
+                    .send_kick(game_id.to_string(), persona_id, reason.to_string())
+                    .await
+                {
+                    log(&err);
+                }
+            }
+            for _ in 0..verification.checks {
+                sleep(verification.interval).await;
+                match self
+                    .get_roster(game_id, &GametoolsPlayers::single(persona_id))
+                    .await
+                {
+                    Ok(players) if !players.contains(persona_id) => return true,
+                    Ok(_) => {}
+                    Err(err) => log(&err),
+                }
+            }
+        }
+        false
+    }
+
     pub async fn kick_player(
         &self,
         game_id: String,
-        persona_id: String,
+        persona_id: u64,
         player_name: String,
+        banned_weapon: String,
         reason: String,
         weapon_class: WeaponClasses,
-        kick_record: Arc<Mutex<PlayerKickHistoryRecord>>,
+        action: EscalationAction,
+        offences: u64,
+        context: KickContext,
+        kick_record: Arc<Mutex<HistoryStore>>,
         bot_stats: Arc<RwLock<BotStats>>,
         config: &Config,
+        server_config: &ServerConfig,
     ) -> Result<(), KickbotError> {
-        let params: HashMap<&str, Value> = HashMap::from([
-            ("game", Value::String("tunguska".to_string())),
-            ("gameId", Value::String(game_id)),
-            ("personaId", Value::String(persona_id.clone())),
-            ("reason", Value::String(reason.to_string())),
-        ]);
-        let body = rpc_request("RSP.kickPlayer".to_string(), params);
-        match self
-            .client
-            .post(endpoints::RPC_HOST)
-            .headers(self.rpc_header.clone())
-            .json(&body)
-            .send()
-            .await
-            .map_err(|err| BF1ApiError::from(err))?
-            .error_for_status()
-        {
-            Ok(_) => {
-                let mut bot_stats_writer = bot_stats.write().await;
-                bot_stats_writer.players_kicked += 1;
-                update_kick_count(bot_stats_writer.players_kicked);
-                add_to_player_kick_record(
-                    kick_record.lock().await.deref_mut(),
-                    config,
-                    player_name.clone(),
-                    weapon_class,
-                    &config.kick_webhook,
-                    persona_id.as_str(),
+        if let Some(shadow) = &server_config.shadow {
+            if shadow.applies_to(&banned_weapon) {
+                log_info(
+                    format!("[Shadow] Would have kicked {player_name}, reason: {reason}").as_str(),
+                );
+                if let Err(err) = add_to_shadow_history(
+                    &server_config.name,
+                    &player_name,
+                    persona_id.to_string().as_str(),
+                    &banned_weapon,
+                    &reason,
+                ) {
+                    log(&err);
+                }
+                return announce_shadow_kick(
+                    &shadow.webhook,
+                    &server_config.name,
+                    &player_name,
+                    persona_id.to_string().as_str(),
+                    &reason,
                 )
                 .await;
-                announce_kick_success(
-                    &config.kick_webhook,
+            }
+        }
+
+        let entry = rule_name(config, &weapon_class).map(|rule| {
+            KickEntry::new(
+                persona_id,
+                &player_name,
+                &rule,
+                &reason,
+                &server_config.name,
+                &game_id,
+                &context,
+            )
+        });
+
+        let mut banned_at = None;
+        match action {
+            EscalationAction::Warn => {
+                log_info(format!("Warned {player_name}, offence {offences}: {reason}").as_str());
+                // Still an offence so the next one escalates
+                if let Some(mut entry) = entry {
+                    entry.action = KickAction::Warn;
+                    add_to_player_kick_record(
+                        kick_record.deref(),
+                        server_config.kicks_to_ping,
+                        &config.history_policy,
+                        entry,
+                        &server_config.kick_webhook,
+                        None,
+                    )
+                    .await;
+                }
+                return announce_player_warning(
+                    &server_config.kick_webhook,
                     player_name.as_str(),
-                    persona_id.as_str(),
+                    persona_id.to_string().as_str(),
                     reason.as_str(),
+                    offences,
                 )
-                .await
+                .await;
             }
+            EscalationAction::Kick => {}
+            EscalationAction::TempBan(_) | EscalationAction::Ban => {
+                let duration = match action {
+                    EscalationAction::TempBan(duration) => Some(duration),
+                    _ => None,
+                };
+                match self
+                    .ban_player_by_game_id(game_id.clone(), persona_id, reason.clone())
+                    .await
+                {
+                    Ok(server_id) => {
+                        banned_at = Some(Utc::now());
+                        if let Some(duration) = duration {
+                            if let Err(err) = add_temp_ban(TempBan {
+                                server_id,
+                                persona_id,
+                                until: (Utc::now() + duration).timestamp(),
+                            }) {
+                                log(&err);
+                            }
+                        }
+                        if let Err(err) = announce_ban_success(
+                            &server_config.kick_webhook,
+                            player_name.as_str(),
+                            persona_id.to_string().as_str(),
+                            reason.as_str(),
+                            offences,
+                            duration,
+                        )
+                        .await
+                        {
+                            log(&err);
+                        }
+                    }
+                    // Still kick them if the ban didn't go through
+                    Err(err) => {
+                        if let Err(err) = announce_ban_fail(
+                            &server_config.kick_webhook,
+                            player_name.as_str(),
+                            persona_id.to_string().as_str(),
+                            reason.as_str(),
+                            err.to_string().as_str(),
+                        )
+                        .await
+                        {
+                            log(&err);
+                        }
+                    }
+                }
+            }
+        }
+
+        // When the kick or ban went through, the end of the detection to kick latency
+        let banned = banned_at.is_some();
+        let result = match banned_at {
+            Some(banned_at) => Ok(banned_at),
+            None => self
+                .send_kick(game_id.clone(), persona_id, reason.clone())
+                .await
+                .map(|_| Utc::now()),
+        };
+
+        let stats = match result {
+            Ok(_) => self
+                .get_player_stats(persona_id)
+                .await
+                .inspect_err(log)
+                .ok(),
+            Err(_) => None,
+        };
+
+        let kicked_at = match result {
+            Ok(kicked_at) => kicked_at,
             Err(err) => {
-                announce_kick_fail(
-                    &config.kick_webhook,
+                return announce_kick_fail(
+                    &server_config.kick_webhook,
                     player_name.as_str(),
-                    persona_id.as_str(),
+                    persona_id.to_string().as_str(),
                     reason.as_str(),
                     err.to_string().as_str(),
                 )
                 .await
             }
+        };
+
+        // None when verification is off, the kick is taken at its word
+        let confirmed = match &server_config.kick_verification {
+            Some(verification) => Some(
+                self.verify_kick(&game_id, persona_id, &reason, verification)
+                    .await,
+            ),
+            None => None,
+        };
+
+        if let (Some(false), Some(verification)) = (confirmed, &server_config.kick_verification) {
+            if let Err(err) = announce_kick_unconfirmed(
+                &server_config.kick_webhook,
+                player_name.as_str(),
+                persona_id.to_string().as_str(),
+                reason.as_str(),
+                verification.retries + 1,
+            )
+            .await
+            {
+                log(&err);
+            }
+        } else {
+            if !banned {
+                if let Err(err) = announce_kick_success(
+                    &server_config.kick_webhook,
+                    player_name.as_str(),
+                    persona_id.to_string().as_str(),
+                    reason.as_str(),
+                    stats.as_ref(),
+                )
+                .await
+                {
+                    log(&err);
+                }
+            }
+
+            let mut bot_stats_writer = bot_stats.write().await;
+            bot_stats_writer.players_kicked += 1;
+            update_kick_count(bot_stats_writer.players_kicked);
+        }
+
+        // Recorded either way, an unconfirmed kick is still an offence
+        if let Some(mut entry) = entry {
+            entry.kicked_at = kicked_at;
+            entry.confirmed = confirmed;
+            // A ban that didn't go through still ended in a kick
+            entry.action = match banned {
+                true => action.into(),
+                false => KickAction::Kick,
+            };
+            add_to_player_kick_record(
+                kick_record.deref(),
+                server_config.kicks_to_ping,
+                &config.history_policy,
+                entry,
+                &server_config.kick_webhook,
+                stats.as_ref(),
+            )
+            .await;
         }
+        Ok(())
     }
 }
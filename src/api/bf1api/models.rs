@@ -0,0 +1,489 @@
+use crate::api::errors::{BF1ApiError, BF1ApiSubError, RpcErrorCode};
+use crate::console::log;
+use crate::errors::KickbotError;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub const GAME: &str = "tunguska";
+
+// Battlelog is inconsistent about sending IDs as strings or numbers, accept both
+fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        Number(u64),
+        String(String),
+    }
+
+    match Id::deserialize(deserializer)? {
+        Id::Number(id) => Ok(id),
+        Id::String(id) => id.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RpcError {
+    pub code: i64,
+    #[serde(default)]
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl From<RpcError> for BF1ApiError {
+    fn from(err: RpcError) -> Self {
+        BF1ApiSubError::RpcError {
+            code: RpcErrorCode::from(err.code),
+            message: err.message,
+        }
+        .into()
+    }
+}
+
+// Battlelog reports failures as a JSON-RPC error object with a 200 status
+#[derive(Deserialize, Debug)]
+pub struct RpcResponse<T> {
+    pub result: Option<T>,
+    pub error: Option<RpcError>,
+}
+
+impl<T> RpcResponse<T> {
+    pub fn into_result(self) -> Result<T, BF1ApiError> {
+        if let Some(err) = self.error {
+            return Err(err.into());
+        }
+        self.result
+            .ok_or(BF1ApiSubError::JsonError("Didn't find 'result' field".to_string()).into())
+    }
+
+    pub fn into_ack(self) -> Result<(), BF1ApiError> {
+        match self.error {
+            Some(err) => Err(err.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+pub type RpcAck = RpcResponse<IgnoredAny>;
+
+// Requests
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthCodeParams {
+    pub auth_code: String,
+    pub locale: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PersonaIdsParams {
+    pub game: &'static str,
+    pub persona_ids: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GameIdParams {
+    pub game: &'static str,
+    pub game_id: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchServersParams {
+    pub filter_json: String,
+    pub game: &'static str,
+    pub limit: String,
+    pub protocol_version: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KickPlayerParams {
+    pub game: &'static str,
+    pub game_id: String,
+    pub persona_id: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerPersonaParams {
+    pub game: &'static str,
+    pub server_id: String,
+    pub persona_id: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MovePlayerParams {
+    pub game: &'static str,
+    pub game_id: String,
+    pub persona_id: String,
+    pub team_id: String,
+    pub force_kill: bool,
+    pub move_party: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChooseLevelParams {
+    pub game: &'static str,
+    pub persisted_game_id: String,
+    pub level_index: String,
+}
+
+// Responses
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvIdViaAuthCode {
+    pub session_id: String,
+    #[serde(deserialize_with = "deserialize_id")]
+    pub persona_id: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Persona {
+    #[serde(deserialize_with = "deserialize_id")]
+    pub persona_id: u64,
+    pub display_name: String,
+}
+
+pub type PersonasByIds = HashMap<String, Persona>;
+
+pub type ServersByPersonaIds = HashMap<String, Option<GameServer>>;
+
+#[derive(Deserialize, Debug)]
+pub struct IdentityPersonas {
+    pub personas: IdentityPersonaList,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IdentityPersonaList {
+    #[serde(default)]
+    pub persona: Vec<IdentityPersona>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityPersona {
+    #[serde(deserialize_with = "deserialize_id")]
+    pub persona_id: u64,
+    #[serde(default)]
+    pub display_name: String,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Slot {
+    #[serde(default)]
+    pub current: u64,
+    #[serde(default)]
+    pub max: u64,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Slots {
+    #[serde(rename = "Soldier", default)]
+    pub soldier: Slot,
+    #[serde(rename = "Queue", default)]
+    pub queue: Slot,
+    #[serde(rename = "Spectator", default)]
+    pub spectator: Slot,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RotationMap {
+    #[serde(default)]
+    pub map_pretty_name: String,
+    #[serde(default)]
+    pub mode_pretty_name: String,
+    #[serde(default)]
+    pub map_image: String,
+}
+
+// Setting codes to their value, e.g. "2KIT": "on" or "BTSC": "100". Numbers are kept as text
+// so one odd value can't fail the whole server lookup
+fn deserialize_setting_values<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, String>, D::Error> {
+    let values = HashMap::<String, Value>::deserialize(deserializer)?;
+    Ok(values
+        .into_iter()
+        .map(|(code, value)| match value {
+            Value::String(value) => (code, value),
+            value => (code, value.to_string()),
+        })
+        .collect())
+}
+
+// The server's custom settings, grouped the way the server browser shows them
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct GameSettings {
+    #[serde(
+        default,
+        rename = "Kits",
+        deserialize_with = "deserialize_setting_values"
+    )]
+    pub kits: HashMap<String, String>,
+    #[serde(
+        default,
+        rename = "Misc",
+        deserialize_with = "deserialize_setting_values"
+    )]
+    pub misc: HashMap<String, String>,
+    #[serde(
+        default,
+        rename = "Scales",
+        deserialize_with = "deserialize_setting_values"
+    )]
+    pub scales: HashMap<String, String>,
+    #[serde(
+        default,
+        rename = "Vehicles",
+        deserialize_with = "deserialize_setting_values"
+    )]
+    pub vehicles: HashMap<String, String>,
+    #[serde(
+        default,
+        rename = "Weapons",
+        deserialize_with = "deserialize_setting_values"
+    )]
+    pub weapons: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GameServer {
+    pub game_id: String,
+    #[serde(default)]
+    pub guid: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub map_name: String,
+    #[serde(default)]
+    pub map_name_pretty: String,
+    #[serde(default)]
+    pub map_mode: String,
+    #[serde(default)]
+    pub map_mode_pretty: String,
+    pub slots: Slots,
+    #[serde(default)]
+    pub rotation: Vec<RotationMap>,
+    #[serde(default)]
+    pub settings: GameSettings,
+}
+
+impl GameServer {
+    // Position of the current map in the rotation, the same map can appear more than once
+    // so this is only the first match
+    pub fn rotation_index(&self) -> Option<usize> {
+        self.rotation.iter().position(|map| {
+            map.map_pretty_name == self.map_name_pretty
+                && map.mode_pretty_name == self.map_mode_pretty
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchServers {
+    #[serde(default)]
+    pub gameservers: Vec<GameServer>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RspServer {
+    #[serde(deserialize_with = "deserialize_id")]
+    pub server_id: u64,
+    #[serde(default)]
+    pub persisted_game_id: String,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerSettings {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub map_rotation_id: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RspInfo {
+    pub server: RspServer,
+    #[serde(default)]
+    pub server_settings: ServerSettings,
+    pub owner: Option<Persona>,
+    #[serde(default)]
+    pub admin_list: Vec<Persona>,
+    #[serde(default)]
+    pub vip_list: Vec<Persona>,
+    #[serde(default)]
+    pub banned_list: Vec<Persona>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FullServerDetails {
+    pub server_info: GameServer,
+    // Only present when we have admin rights on the server
+    pub rsp_info: Option<RspInfo>,
+}
+
+impl FullServerDetails {
+    pub fn rsp_info(&self) -> Result<&RspInfo, BF1ApiError> {
+        self.rsp_info.as_ref().ok_or(
+            BF1ApiSubError::ResponseError(format!(
+                "No admin info for server {}, is the bot an admin?",
+                self.server_info.name
+            ))
+            .into(),
+        )
+    }
+}
+
+// api.gametools.network
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ServerPlayer {
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_id")]
+    pub player_id: u64,
+    #[serde(default)]
+    pub platoon: String,
+    #[serde(default)]
+    pub rank: u32,
+    #[serde(default)]
+    pub latency: u32,
+    #[serde(default)]
+    pub join_time: u64,
+}
+
+// One bad player shouldn't lose the whole team, they're skipped like the old manual parsing did
+fn deserialize_players<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ServerPlayer>, D::Error> {
+    let players = Vec::<Value>::deserialize(deserializer)?;
+    Ok(players
+        .into_iter()
+        .filter_map(|player| {
+            serde_json::from_value(player.clone())
+                .inspect_err(|err| {
+                    log(&KickbotError::JsonError(format!(
+                        "Skipping roster entry {}, {}",
+                        player, err
+                    )))
+                })
+                .ok()
+        })
+        .collect())
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct GametoolsTeam {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, deserialize_with = "deserialize_players")]
+    pub players: Vec<ServerPlayer>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct GametoolsPlayers {
+    #[serde(default)]
+    pub teams: Vec<GametoolsTeam>,
+}
+
+impl GametoolsPlayers {
+    // Just the one player, for checking whether they're still on the server
+    pub fn single(persona_id: u64) -> Self {
+        GametoolsPlayers {
+            teams: vec![GametoolsTeam {
+                name: String::new(),
+                players: vec![ServerPlayer {
+                    player_id: persona_id,
+                    ..Default::default()
+                }],
+            }],
+        }
+    }
+
+    pub fn contains(&self, persona_id: u64) -> bool {
+        self.teams
+            .iter()
+            .flat_map(|team| team.players.iter())
+            .any(|player| player.player_id == persona_id)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WeaponStats {
+    #[serde(default)]
+    pub weapon_name: String,
+    #[serde(default)]
+    pub kills: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleStats {
+    #[serde(default)]
+    pub vehicle_name: String,
+    #[serde(default)]
+    pub kills: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerStats {
+    #[serde(default)]
+    pub rank: u32,
+    #[serde(default)]
+    pub kill_death: f64,
+    #[serde(default)]
+    pub seconds_played: u64,
+    #[serde(default)]
+    pub weapons: Vec<WeaponStats>,
+    #[serde(default)]
+    pub vehicles: Vec<VehicleStats>,
+}
+
+impl PlayerStats {
+    // One line summary for kick embeds, e.g.
+    // Rank 110 | K/D 2.31 | 512h played | Top: SMG 08/18 (5000), Heavy Bomber (1200)
+    pub fn summary(&self) -> String {
+        let top_weapon = self.weapons.iter().max_by_key(|weapon| weapon.kills);
+        let top_vehicle = self.vehicles.iter().max_by_key(|vehicle| vehicle.kills);
+
+        let mut top = vec![];
+        if let Some(weapon) = top_weapon {
+            top.push(format!("{} ({})", weapon.weapon_name, weapon.kills));
+        }
+        if let Some(vehicle) = top_vehicle {
+            top.push(format!("{} ({})", vehicle.vehicle_name, vehicle.kills));
+        }
+
+        let mut summary = format!(
+            "Rank {} | K/D {:.2} | {}h played",
+            self.rank,
+            self.kill_death,
+            self.seconds_played / 3600
+        );
+        if !top.is_empty() {
+            summary = format!("{summary} | Top: {}", top.join(", "));
+        }
+        summary
+    }
+}
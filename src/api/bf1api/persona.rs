@@ -1,71 +1,39 @@
-use crate::api::bf1api::{rpc_request, BF1Api, Lookup};
+use crate::api::bf1api::models::{
+    IdentityPersonas, PersonaIdsParams, PersonasByIds, ServersByPersonaIds, GAME,
+};
+use crate::api::bf1api::BF1Api;
 use crate::api::endpoints;
 use crate::api::errors::{BF1ApiError, BF1ApiSubError};
-use crate::errors::KickbotError;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use serde_json::{Map, Value};
-use std::collections::HashMap;
 use std::str::FromStr;
 
 impl BF1Api {
     pub async fn get_display_names_by_persona_ids(
         &self,
-        persona_ids: Vec<&str>,
+        persona_ids: Vec<u64>,
     ) -> Result<Vec<String>, BF1ApiError> {
-        let values = persona_ids
-            .iter()
-            .map(|id| Value::String(id.to_string()))
-            .collect();
-        let params: HashMap<&str, Value> = HashMap::from([
-            ("game", Value::String("tunguska".to_string())),
-            ("personaIds", values),
-        ]);
-        let body = rpc_request("RSP.getPersonasByIds".to_string(), params);
-
-        let response = self
-            .client
-            .post(endpoints::RPC_HOST)
-            .headers(self.rpc_header.clone())
-            .json(&body)
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let response_json: Map<String, Value> =
-            serde_json::from_str(response.text().await?.as_str())?;
-        let result = response_json.lookup("result")?;
+        let params = PersonaIdsParams {
+            game: GAME,
+            persona_ids: persona_ids.iter().map(|id| id.to_string()).collect(),
+        };
+        let result: PersonasByIds = self.rpc("RSP.getPersonasByIds", params).await?;
 
         let mut display_names: Vec<String> = Vec::new();
 
         for persona_id in persona_ids {
             let persona = result
-                .get(persona_id)
+                .get(&persona_id.to_string())
                 .ok_or(BF1ApiSubError::JsonError(format!(
                     "Didn't find persona with id {} in 'result' field",
                     persona_id
                 )))?;
-            let display_name = persona
-                .get("displayName")
-                .ok_or(BF1ApiSubError::JsonError(format!(
-                    "Display name field not found for persona with id {}",
-                    persona_id
-                )))?
-                .as_str()
-                .ok_or(BF1ApiSubError::JsonError(format!(
-                    "Couldn't parse 'displayName' field as string for persona with id {}",
-                    persona_id
-                )))?
-                .to_string();
-            display_names.push(display_name);
+            display_names.push(persona.display_name.clone());
         }
 
         Ok(display_names)
     }
 
-    pub async fn get_player_persona_by_name(
-        &self,
-        player_name: &str,
-    ) -> Result<String, BF1ApiError> {
+    pub async fn get_player_persona_by_name(&self, player_name: &str) -> Result<u64, BF1ApiError> {
         let mut headers = HeaderMap::new();
         headers.insert(
             HeaderName::from_str("X-Expand-Results").unwrap(),
@@ -84,54 +52,29 @@ impl BF1Api {
             .await?
             .error_for_status()?;
 
-        let response_json: Map<String, Value> =
-            serde_json::from_str(response.text().await?.as_str())?;
+        let identity: IdentityPersonas = serde_json::from_str(response.text().await?.as_str())?;
 
-        let persona = response_json
-            .lookup("personas")?
-            .lookup("persona")?
-            .as_array()
-            .ok_or(BF1ApiSubError::JsonError(
-                "Couldn't parse persona as array".to_string(),
-            ))?[0]
-            .as_object()
-            .ok_or(BF1ApiSubError::JsonError(
-                "Couldn't parse as object".to_string(),
-            ))?;
-        let persona_id = persona.lookup("personaId")?;
+        let persona = identity
+            .personas
+            .persona
+            .first()
+            .ok_or(BF1ApiSubError::JsonError(format!(
+                "No persona found for name {}",
+                player_name
+            )))?;
 
-        Ok(persona_id.to_string())
+        Ok(persona.persona_id)
     }
 
     pub async fn get_servers_by_persona_ids(
         &self,
-        persona_ids: Vec<String>,
-    ) -> Result<(), BF1ApiError> {
-        let json_body = rpc_request(
-            "GameServer.getServersByPersonaIds".to_string(),
-            HashMap::from([
-                ("game", Value::String("tunguska".to_string())),
-                (
-                    "personaIds",
-                    Value::Array(persona_ids.iter().map(|id| Value::String(id.clone())).collect()),
-                ),
-            ]),
-        );
-
-        let response = self
-            .client
-            .post(endpoints::RPC_HOST)
-            .headers(self.rpc_header.clone())
-            .json(&json_body)
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let response_json: Map<String, Value> =
-            serde_json::from_str(response.text().await?.as_str())?;
-
-        println!("{:?}", response_json);
+        persona_ids: Vec<u64>,
+    ) -> Result<ServersByPersonaIds, BF1ApiError> {
+        let params = PersonaIdsParams {
+            game: GAME,
+            persona_ids: persona_ids.iter().map(|id| id.to_string()).collect(),
+        };
 
-        Ok(())
+        self.rpc("GameServer.getServersByPersonaIds", params).await
     }
 }
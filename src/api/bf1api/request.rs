@@ -0,0 +1,158 @@
+use crate::api::bf1api::BF1Api;
+use crate::api::errors::BF1ApiError;
+use rand::Rng;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+#[derive(Debug, Clone)]
+pub struct RequestPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub requests_per_second: f64,
+    pub burst: u32,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        RequestPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+            requests_per_second: 5.0,
+            burst: 10,
+        }
+    }
+}
+
+impl RequestPolicy {
+    // Exponential backoff with full jitter
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        ceiling.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_second: f64,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: &RequestPolicy) -> Self {
+        RateLimiter {
+            capacity: policy.burst.max(1) as f64,
+            refill_per_second: policy.requests_per_second,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Waits until a request to host is allowed
+    pub async fn acquire(&self, host: &str) {
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().await;
+                let bucket = buckets.entry(host.to_string()).or_insert(TokenBucket {
+                    tokens: self.capacity,
+                    last_refill: Instant::now(),
+                });
+
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens =
+                    (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                if self.refill_per_second <= 0.0 {
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_second)
+            };
+            sleep(wait).await;
+        }
+    }
+}
+
+impl BF1Api {
+    // Only use for requests that are safe to send more than once
+    pub(crate) async fn with_retry<T, F, Fut>(
+        &self,
+        host: &str,
+        mut request: F,
+    ) -> Result<T, BF1ApiError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, BF1ApiError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            self.rate_limiter.acquire(host).await;
+            match request().await {
+                Err(err) if err.is_transient() && attempt < self.policy.max_retries => {
+                    sleep(self.policy.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub(crate) async fn once<T, Fut>(&self, host: &str, request: Fut) -> Result<T, BF1ApiError>
+    where
+        Fut: Future<Output = Result<T, BF1ApiError>>,
+    {
+        self.rate_limiter.acquire(host).await;
+        request.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_stays_under_the_doubling_ceiling() {
+        let policy = RequestPolicy::default();
+        for attempt in 0..4 {
+            let ceiling = policy.base_delay * 2u32.pow(attempt);
+            for _ in 0..50 {
+                assert!(policy.backoff(attempt) <= ceiling);
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped_at_max_delay() {
+        let policy = RequestPolicy::default();
+        for attempt in [5, 10, 31, 32, u32::MAX] {
+            assert!(policy.backoff(attempt) <= policy.max_delay);
+        }
+    }
+
+    #[test]
+    fn backoff_without_a_base_delay_is_immediate() {
+        let policy = RequestPolicy {
+            base_delay: Duration::ZERO,
+            ..RequestPolicy::default()
+        };
+        assert_eq!(policy.backoff(3), Duration::ZERO);
+    }
+}
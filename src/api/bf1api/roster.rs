@@ -0,0 +1,186 @@
+use crate::api::bf1api::models::{GametoolsPlayers, GametoolsTeam};
+use crate::api::bf1api::BF1Api;
+use crate::api::errors::{BF1ApiError, BF1ApiSubError};
+use crate::console::log_info;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// Gametools is the only provider that lists the whole server, the companion API can only stand
+// in for it by confirming players gametools already found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RosterProvider {
+    Gametools,
+    Rpc,
+}
+
+impl Display for RosterProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RosterProvider::Gametools => write!(f, "gametools"),
+            RosterProvider::Rpc => write!(f, "rpc"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RosterPolicy {
+    // Consecutive gametools failures before switching to the companion API
+    pub failover_after: u32,
+    // How long to stay on the companion API before trying gametools again
+    pub retry_primary_after: Duration,
+}
+
+impl Default for RosterPolicy {
+    fn default() -> Self {
+        RosterPolicy {
+            failover_after: 3,
+            retry_primary_after: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FailoverState {
+    failures: u32,
+    failed_over_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct RosterFailover {
+    policy: RosterPolicy,
+    state: Mutex<FailoverState>,
+}
+
+impl RosterFailover {
+    pub fn new(policy: RosterPolicy) -> Self {
+        RosterFailover {
+            policy,
+            state: Mutex::new(FailoverState::default()),
+        }
+    }
+
+    async fn current(&self) -> RosterProvider {
+        let state = self.state.lock().await;
+        match state.failed_over_at {
+            Some(failed_over_at) if failed_over_at.elapsed() < self.policy.retry_primary_after => {
+                RosterProvider::Rpc
+            }
+            _ => RosterProvider::Gametools,
+        }
+    }
+
+    async fn record(&self, provider: RosterProvider, succeeded: bool) {
+        if provider != RosterProvider::Gametools {
+            return;
+        }
+
+        let mut state = self.state.lock().await;
+        if succeeded {
+            if state.failed_over_at.take().is_some() {
+                log_info(format!("Roster provider {} is back", provider).as_str());
+            }
+            state.failures = 0;
+            return;
+        }
+
+        state.failures += 1;
+        if state.failures >= self.policy.failover_after {
+            if state.failed_over_at.is_none() {
+                log_info(
+                    format!(
+                        "Roster provider {} failed {} times, switching to {}",
+                        provider,
+                        state.failures,
+                        RosterProvider::Rpc
+                    )
+                    .as_str(),
+                );
+            }
+            state.failed_over_at = Some(Instant::now());
+        }
+    }
+}
+
+impl BF1Api {
+    // The companion API has no roster call, but GameServer.getServersByPersonaIds says where
+    // each player is. Keeps the players from the last roster that are still on this server,
+    // anyone who joined since only shows up once gametools is back
+    pub async fn get_players_by_rpc(
+        &self,
+        game_id: &str,
+        last_roster: &GametoolsPlayers,
+    ) -> Result<GametoolsPlayers, BF1ApiError> {
+        let persona_ids: Vec<u64> = last_roster
+            .teams
+            .iter()
+            .flat_map(|team| team.players.iter())
+            .map(|player| player.player_id)
+            .collect();
+        if persona_ids.is_empty() {
+            return Err(
+                BF1ApiSubError::ResponseError("No known players to look up".to_string()).into(),
+            );
+        }
+
+        let servers = self.get_servers_by_persona_ids(persona_ids).await?;
+        let on_server = |persona_id: u64| {
+            servers
+                .get(&persona_id.to_string())
+                .and_then(Option::as_ref)
+                .is_some_and(|game_server| game_server.game_id == game_id)
+        };
+        Ok(GametoolsPlayers {
+            teams: last_roster
+                .teams
+                .iter()
+                .map(|team| GametoolsTeam {
+                    name: team.name.clone(),
+                    players: team
+                        .players
+                        .iter()
+                        .filter(|player| on_server(player.player_id))
+                        .cloned()
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+
+    async fn get_players_from(
+        &self,
+        provider: RosterProvider,
+        game_id: &str,
+        last_roster: &GametoolsPlayers,
+    ) -> Result<GametoolsPlayers, BF1ApiError> {
+        match provider {
+            RosterProvider::Gametools => self.get_players_by_game_id(game_id).await,
+            RosterProvider::Rpc => self.get_players_by_rpc(game_id, last_roster).await,
+        }
+    }
+
+    // Uses gametools, falling back to the companion API once it keeps failing. last_roster is
+    // what the companion API checks against, players who join during the outage are missing
+    // until gametools is back
+    pub async fn get_roster(
+        &self,
+        game_id: &str,
+        last_roster: &GametoolsPlayers,
+    ) -> Result<GametoolsPlayers, BF1ApiError> {
+        let provider = self.roster.current().await;
+        let result = self.get_players_from(provider, game_id, last_roster).await;
+        self.roster.record(provider, result.is_ok()).await;
+
+        match result {
+            Err(err) if provider == RosterProvider::Gametools => {
+                // Only cover for gametools once it has failed enough times to fail over
+                if self.roster.current().await == provider {
+                    return Err(err);
+                }
+                self.get_players_from(RosterProvider::Rpc, game_id, last_roster)
+                    .await
+            }
+            result => result,
+        }
+    }
+}
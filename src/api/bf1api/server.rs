@@ -1,14 +1,19 @@
-use crate::api::bf1api::{rpc_request, BF1Api, Lookup};
+use crate::api::bf1api::models::{
+    ChooseLevelParams, FullServerDetails, GameIdParams, GameServer, GametoolsPlayers,
+    GametoolsTeam, MovePlayerParams, RotationMap, SearchServers, SearchServersParams, ServerPlayer,
+    ServerSettings, GAME,
+};
+use crate::api::bf1api::BF1Api;
 use crate::api::endpoints;
 use crate::api::errors::{BF1ApiError, BF1ApiSubError};
-use reqwest::header::{HeaderMap, COOKIE};
 use serde::Deserialize;
-use serde_json::{Map, Value};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Deserialize, Debug, Default, Clone)]
 pub struct ServerDetails {
     pub game_id: String,
+    pub guid: String,
+    pub server_id: Option<u64>,
     pub name: String,
 
     pub max_player_count: u64,
@@ -16,12 +21,19 @@ pub struct ServerDetails {
     pub spectator_count: u64,
     pub max_spectator_count: u64,
     pub map: String,
+    pub game_mode: String,
+    pub rotation: Vec<RotationMap>,
+    pub rotation_index: Option<usize>,
+    pub settings: ServerSettings,
 
-    pub team1: HashMap<String, String>,
+    pub team1: HashMap<String, ServerPlayer>,
     pub team1_name: String,
-    pub team2: HashMap<String, String>,
+    pub team2: HashMap<String, ServerPlayer>,
     pub team2_name: String,
     pub updated: bool,
+
+    pub admins: HashSet<u64>,
+    pub vips: HashSet<u64>,
 }
 
 impl ServerDetails {
@@ -38,60 +50,52 @@ impl ServerDetails {
         }
     }
 
+    // The current roster in the shape the providers return it
+    pub fn last_roster(&self) -> GametoolsPlayers {
+        let team = |name: &String, players: &HashMap<String, ServerPlayer>| GametoolsTeam {
+            name: name.clone(),
+            players: players.values().cloned().collect(),
+        };
+        GametoolsPlayers {
+            teams: vec![
+                team(&self.team1_name, &self.team1),
+                team(&self.team2_name, &self.team2),
+            ],
+        }
+    }
+
     pub async fn update_players(&mut self, api: &BF1Api) -> Result<(), BF1ApiError> {
-        let params = [("gameID", self.game_id.clone())];
-
-        let url = reqwest::Url::parse_with_params(endpoints::GAMETOOLS, params).unwrap();
-        let response = api.client.get(url).send().await?.error_for_status()?;
-
-        let response_json: Map<String, Value> =
-            serde_json::from_str(response.text().await?.as_str())?;
-
-        let teams = response_json.lookup("teams")?.as_array().unwrap();
-        let team1Name = teams[0].lookup("name")?.as_str().unwrap();
-        let team1Object = teams[0]
-            .as_object()
-            .unwrap()
-            .lookup("players")?
-            .as_array()
-            .unwrap();
-        let team2Name = teams[1].lookup("name")?.as_str().unwrap();
-        let team2Object = teams[1]
-            .as_object()
-            .unwrap()
-            .lookup("players")?
-            .as_array()
-            .unwrap();
-
-        let GetTeam = |team: &mut HashMap<String, String>, teamObject: &Vec<Value>| {
-            for player in teamObject.iter() {
+        let players = api.get_roster(&self.game_id, &self.last_roster()).await?;
+
+        let [team1, team2] = match players.teams.as_slice() {
+            [team1, team2, ..] => [team1, team2],
+            _ => {
+                return Err(BF1ApiSubError::JsonError(format!(
+                    "Expected 2 teams, got {}",
+                    players.teams.len()
+                ))
+                .into())
+            }
+        };
+
+        let get_team = |team: &mut HashMap<String, ServerPlayer>, players: &Vec<ServerPlayer>| {
+            for player in players.iter() {
                 // Need to append platoon tag because it shows up in the spectator player name view
-                let player_object = player.as_object().unwrap();
-                let mut player_name = match player_object.get("name") {
-                    None => continue,
-                    Some(player_name) => player_name.to_string().replace("\"", ""),
+                let player_name = if player.platoon.is_empty() {
+                    player.name.clone()
+                } else {
+                    format!("[{}]{}", player.platoon, player.name)
                 };
-                let player_id = match player_object.get("player_id") {
-                    None => continue,
-                    Some(player_id) => player_id,
-                }
-                .to_string();
-                if let Some(platoon) = player.get("platoon") {
-                    let platoon_string = platoon.to_string().replace("\"", "");
-                    if !platoon_string.is_empty() {
-                        player_name = format!("[{}]{}", platoon_string, player_name);
-                    }
-                }
-                team.insert(player_name, player_id);
+                team.insert(player_name, player.clone());
             }
         };
 
         self.team1.clear();
         self.team2.clear();
-        GetTeam(&mut self.team1, &team1Object);
-        GetTeam(&mut self.team2, &team2Object);
-        self.team1_name = team1Name.to_string();
-        self.team2_name = team2Name.to_string();
+        get_team(&mut self.team1, &team1.players);
+        get_team(&mut self.team2, &team2.players);
+        self.team1_name = team1.name.clone();
+        self.team2_name = team2.name.clone();
 
         Ok(())
     }
@@ -101,133 +105,271 @@ impl ServerDetails {
         api: &BF1Api,
         mut game_id: Option<String>,
     ) -> Result<(), BF1ApiError> {
-        let gameId = game_id.get_or_insert(self.game_id.clone());
-        let result = api.get_server_by_game_id(gameId).await?;
-        let slots = result.lookup("slots")?;
-        self.decode_server_details_json(&result, &slots)
+        let game_id = game_id.get_or_insert(self.game_id.clone());
+        let full_server_details = api.get_full_server_details(game_id).await?;
+        self.apply_full_server_details(&full_server_details);
+        Ok(())
     }
 
-    pub fn decode_server_details_json(
-        &mut self,
-        gameservers: &Value,
-        slots: &Value,
-    ) -> Result<(), BF1ApiError> {
-        self.game_id = gameservers.lookup("gameId")?.as_str().unwrap().to_string();
-        self.queue_count = slots.lookup("Queue")?.lookup("current")?.as_u64().unwrap();
-        self.spectator_count = slots
-            .lookup("Spectator")?
-            .lookup("current")?
-            .as_u64()
-            .unwrap();
-        self.map = gameservers
-            .lookup("mapNamePretty")?
-            .as_str()
-            .unwrap()
-            .to_string();
-        Ok(())
+    pub fn apply_full_server_details(&mut self, full_server_details: &FullServerDetails) {
+        self.apply_game_server(&full_server_details.server_info);
+
+        if let Some(rsp_info) = &full_server_details.rsp_info {
+            self.server_id = Some(rsp_info.server.server_id);
+            if !rsp_info.server.persisted_game_id.is_empty() {
+                self.guid = rsp_info.server.persisted_game_id.clone();
+            }
+            self.settings = rsp_info.server_settings.clone();
+            self.admins = rsp_info
+                .admin_list
+                .iter()
+                .chain(rsp_info.owner.iter())
+                .map(|persona| persona.persona_id)
+                .collect();
+            self.vips = rsp_info
+                .vip_list
+                .iter()
+                .map(|persona| persona.persona_id)
+                .collect();
+        }
+    }
+
+    // e.g. "3/8", or "?/8" if the current map couldn't be found in the rotation
+    pub fn rotation_position(&self) -> String {
+        let index = match self.rotation_index {
+            Some(index) => (index + 1).to_string(),
+            None => "?".to_string(),
+        };
+        format!("{}/{}", index, self.rotation.len())
+    }
+
+    pub fn is_admin(&self, persona_id: u64) -> bool {
+        self.admins.contains(&persona_id)
+    }
+
+    pub fn is_vip(&self, persona_id: u64) -> bool {
+        self.vips.contains(&persona_id)
+    }
+
+    pub fn apply_game_server(&mut self, game_server: &GameServer) {
+        self.game_id = game_server.game_id.clone();
+        if !game_server.guid.is_empty() {
+            self.guid = game_server.guid.clone();
+        }
+        self.game_mode = game_server.map_mode_pretty.clone();
+        if !game_server.rotation.is_empty() {
+            self.rotation = game_server.rotation.clone();
+        }
+        self.rotation_index = game_server.rotation_index();
+        self.queue_count = game_server.slots.queue.current;
+        self.spectator_count = game_server.slots.spectator.current;
+        self.map = game_server.map_name_pretty.clone();
     }
 }
 
 impl BF1Api {
-    pub async fn get_server_by_name(
+    pub async fn get_players_by_game_id(
+        &self,
+        game_id: &str,
+    ) -> Result<GametoolsPlayers, BF1ApiError> {
+        let params = [("gameID", game_id)];
+        let url = reqwest::Url::parse_with_params(endpoints::GAMETOOLS, params)
+            .map_err(|err| BF1ApiSubError::ResponseError(err.to_string()))?;
+
+        self.with_retry(endpoints::GAMETOOLS, || async {
+            let response = self
+                .client
+                .get(url.clone())
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(serde_json::from_str(response.text().await?.as_str())?)
+        })
+        .await
+    }
+
+    // Matches on guid when it's known, otherwise the name has to match exactly.
+    // The search filter is only a partial name match so every result is checked
+    pub async fn get_server(
         &self,
         server_name: &str,
+        guid: Option<&str>,
     ) -> Result<ServerDetails, BF1ApiError> {
-        let params: HashMap<&str, Value> = HashMap::from([
-            (
-                "filterJson",
-                Value::String(format!("{{\"version\":6,\"name\":\"{}\"}}", server_name)),
-            ),
-            ("game", Value::String("tunguska".to_string())),
-            ("limit", Value::String("30".to_string())),
-            ("protocolVersion", Value::String("3779779".to_string())),
-        ]);
-        let body = rpc_request("GameServer.searchServers".to_string(), params);
-        let response = self
-            .client
-            .post(endpoints::RPC_HOST)
-            .headers(self.rpc_header.clone())
-            .json(&body)
-            .send()
-            .await?
-            .error_for_status()?;
-        let response_json: Map<String, Value> =
-            serde_json::from_str(response.text().await?.as_str())?;
-
-        let result = response_json.lookup("result")?;
-
-        let gameservers = result
-            .get("gameservers")
-            .ok_or(BF1ApiSubError::JsonError(
-                "Couldn't get gameservers".to_string(),
-            ))?
-            .as_array()
-            .ok_or(BF1ApiSubError::JsonError(
-                "Couldn't parse to array".to_string(),
-            ))?
-            .get(0)
-            .ok_or(BF1ApiSubError::JsonError(
-                "Couldn't get get first result".to_string(),
-            ))?;
+        let params = SearchServersParams {
+            filter_json: format!("{{\"version\":6,\"name\":\"{}\"}}", server_name),
+            game: GAME,
+            limit: "30".to_string(),
+            protocol_version: "3779779".to_string(),
+        };
+        let result: SearchServers = self.rpc("GameServer.searchServers", params).await?;
+
+        let game_server = select_game_server(&result.gameservers, server_name, guid)?;
 
         let mut server = ServerDetails::default();
-        let slots = gameservers.lookup("slots")?;
-        server.name = gameservers.lookup("name")?.as_str().unwrap().to_string();
-        server.max_player_count = slots.lookup("Soldier")?.lookup("max")?.as_u64().unwrap();
-        server.max_spectator_count = slots.lookup("Spectator")?.lookup("max")?.as_u64().unwrap();
+        server.name = game_server.name.clone();
+        server.max_player_count = game_server.slots.soldier.max;
+        server.max_spectator_count = game_server.slots.spectator.max;
 
-        server.decode_server_details_json(gameservers, slots)?;
+        server.apply_game_server(game_server);
 
+        server.update_server_details(&self, None).await?;
         server.update_players(&self).await?;
 
         Ok(server)
     }
 
-    pub async fn get_server_by_game_id(&self, game_id: &String) -> Result<Value, BF1ApiError> {
-        let json_body = rpc_request(
-            "GameServer.getServerDetails".to_string(),
-            HashMap::from([
-                ("game", Value::String("tunguska".to_string())),
-                ("gameId", Value::String(game_id.to_string())),
-            ]),
-        );
+    pub async fn get_server_by_game_id(&self, game_id: &String) -> Result<GameServer, BF1ApiError> {
+        let params = GameIdParams {
+            game: GAME,
+            game_id: game_id.to_string(),
+        };
 
-        let response = self
-            .client
-            .post(endpoints::RPC_HOST)
-            .headers(self.rpc_header.clone())
-            .json(&json_body)
-            .send()
-            .await?
-            .error_for_status()?;
+        self.rpc("GameServer.getServerDetails", params).await
+    }
 
-        let response_json: Map<String, Value> =
-            serde_json::from_str(response.text().await?.as_str())?;
+    pub async fn get_full_server_details(
+        &self,
+        game_id: &str,
+    ) -> Result<FullServerDetails, BF1ApiError> {
+        let params = GameIdParams {
+            game: GAME,
+            game_id: game_id.to_string(),
+        };
+
+        self.rpc("GameServer.getFullServerDetails", params).await
+    }
+
+    // team_id is the team to move them to, 1 or 2
+    pub async fn move_player(
+        &self,
+        game_id: String,
+        persona_id: u64,
+        team_id: u8,
+    ) -> Result<(), BF1ApiError> {
+        let params = MovePlayerParams {
+            game: GAME,
+            game_id,
+            persona_id: persona_id.to_string(),
+            team_id: team_id.to_string(),
+            force_kill: true,
+            move_party: false,
+        };
 
-        Ok(response_json
-            .get("result")
-            .ok_or(BF1ApiSubError::JsonError("Couldn't get result".to_string()))?
-            .clone())
+        self.rpc_ack("RSP.movePlayer", params).await
+    }
+
+    // Switches to the map at level_index in the server's rotation
+    pub async fn choose_level(
+        &self,
+        persisted_game_id: String,
+        level_index: usize,
+    ) -> Result<(), BF1ApiError> {
+        let params = ChooseLevelParams {
+            game: GAME,
+            persisted_game_id,
+            level_index: level_index.to_string(),
+        };
+
+        self.rpc_ack("RSP.chooseLevel", params).await
     }
 
     pub async fn leave_game(&self, game_id: String) -> Result<(), BF1ApiError> {
-        let params: HashMap<&str, Value> = HashMap::from([
-            ("game", Value::String("tunguska".to_string())),
-            ("gameId", Value::String(game_id)),
-        ]);
-
-        let body = rpc_request("Game.joinGame".to_string(), params);
-
-        let response = self
-            .client
-            .post(endpoints::RPC_HOST)
-            .headers(self.rpc_header.clone())
-            .json(&body)
-            .send()
-            .await?
-            .error_for_status()?;
-        println!("Response: {}", response.text().await?);
+        let params = GameIdParams {
+            game: GAME,
+            game_id,
+        };
 
-        Ok(())
+        self.rpc_ack("Game.joinGame", params).await
+    }
+}
+
+fn select_game_server<'a>(
+    game_servers: &'a [GameServer],
+    server_name: &str,
+    guid: Option<&str>,
+) -> Result<&'a GameServer, BF1ApiError> {
+    // An empty guid would only match servers the search returned without one
+    let (matches, wanted): (Vec<&GameServer>, String) = match guid.filter(|guid| !guid.is_empty()) {
+        Some(guid) => (
+            game_servers
+                .iter()
+                .filter(|game_server| game_server.guid == guid)
+                .collect(),
+            format!("guid {}", guid),
+        ),
+        None => (
+            game_servers
+                .iter()
+                .filter(|game_server| game_server.name == server_name)
+                .collect(),
+            format!("name \"{}\"", server_name),
+        ),
+    };
+
+    match matches.as_slice() {
+        [game_server] => Ok(game_server),
+        [] => Err(BF1ApiSubError::ServerMatchError(format!(
+            "No server with {} in {} search results for \"{}\"",
+            wanted,
+            game_servers.len(),
+            server_name
+        ))
+        .into()),
+        _ => Err(BF1ApiSubError::ServerMatchError(format!(
+            "{} servers match {}, set a guid in config to pick one: {}",
+            matches.len(),
+            wanted,
+            matches
+                .iter()
+                .map(|game_server| format!("{} ({})", game_server.name, game_server.guid))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ))
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn game_server(name: &str, guid: &str) -> GameServer {
+        serde_json::from_value(json!({
+            "gameId": "1",
+            "guid": guid,
+            "name": name,
+            "slots": {}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn selects_by_guid_over_name() {
+        let servers = vec![game_server("![VG]", "a"), game_server("![VG]", "b")];
+        let selected = select_game_server(&servers, "![VG]", Some("b")).unwrap();
+        assert_eq!(selected.guid, "b");
+    }
+
+    #[test]
+    fn selects_exact_name_only() {
+        let servers = vec![game_server("![VG] 2", "a"), game_server("![VG]", "b")];
+        let selected = select_game_server(&servers, "![VG]", None).unwrap();
+        assert_eq!(selected.guid, "b");
+    }
+
+    #[test]
+    fn rejects_no_or_ambiguous_matches() {
+        let servers = vec![game_server("![VG]", "a"), game_server("![VG]", "b")];
+        assert!(select_game_server(&servers, "![VG]", None).is_err());
+        assert!(select_game_server(&servers, "![VG]", Some("c")).is_err());
+        assert!(select_game_server(&servers, "other", None).is_err());
+    }
+
+    #[test]
+    fn empty_guid_falls_back_to_name() {
+        let servers = vec![game_server("![VG]", ""), game_server("other", "")];
+        let selected = select_game_server(&servers, "![VG]", Some("")).unwrap();
+        assert_eq!(selected.name, "![VG]");
     }
 }
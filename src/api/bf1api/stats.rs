@@ -0,0 +1,43 @@
+use crate::api::bf1api::models::PlayerStats;
+use crate::api::bf1api::BF1Api;
+use crate::api::endpoints;
+use crate::api::errors::{BF1ApiError, BF1ApiSubError};
+use std::time::{Duration, Instant};
+
+const STATS_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+impl BF1Api {
+    pub async fn get_player_stats(&self, persona_id: u64) -> Result<PlayerStats, BF1ApiError> {
+        if let Some((fetched, stats)) = self.stats_cache.lock().await.get(&persona_id) {
+            if fetched.elapsed() < STATS_CACHE_TTL {
+                return Ok(stats.clone());
+            }
+        }
+
+        let params = [
+            ("playerid", persona_id.to_string()),
+            ("format_values", "false".to_string()),
+            ("lang", "en-us".to_string()),
+        ];
+        let url = reqwest::Url::parse_with_params(endpoints::GAMETOOLS_STATS, params)
+            .map_err(|err| BF1ApiSubError::ResponseError(err.to_string()))?;
+
+        let stats: PlayerStats = self
+            .with_retry(endpoints::GAMETOOLS, || async {
+                let response = self
+                    .client
+                    .get(url.clone())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(serde_json::from_str(response.text().await?.as_str())?)
+            })
+            .await?;
+
+        let mut stats_cache = self.stats_cache.lock().await;
+        stats_cache.retain(|_, (fetched, _)| fetched.elapsed() < STATS_CACHE_TTL);
+        stats_cache.insert(persona_id, (Instant::now(), stats.clone()));
+
+        Ok(stats)
+    }
+}
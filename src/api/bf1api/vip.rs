@@ -0,0 +1,43 @@
+use crate::api::bf1api::models::{Persona, ServerPersonaParams, GAME};
+use crate::api::bf1api::BF1Api;
+use crate::api::errors::BF1ApiError;
+
+impl BF1Api {
+    pub async fn add_vip(&self, server_id: u64, persona_id: u64) -> Result<(), BF1ApiError> {
+        let params = ServerPersonaParams {
+            game: GAME,
+            server_id: server_id.to_string(),
+            persona_id: persona_id.to_string(),
+        };
+
+        self.rpc_ack("RSP.addServerVip", params).await
+    }
+
+    pub async fn remove_vip(&self, server_id: u64, persona_id: u64) -> Result<(), BF1ApiError> {
+        let params = ServerPersonaParams {
+            game: GAME,
+            server_id: server_id.to_string(),
+            persona_id: persona_id.to_string(),
+        };
+
+        self.rpc_ack("RSP.removeServerVip", params).await
+    }
+
+    pub async fn get_vip_list(&self, game_id: &str) -> Result<Vec<Persona>, BF1ApiError> {
+        Ok(self
+            .get_full_server_details(game_id)
+            .await?
+            .rsp_info()?
+            .vip_list
+            .clone())
+    }
+
+    pub async fn get_admin_list(&self, game_id: &str) -> Result<Vec<Persona>, BF1ApiError> {
+        Ok(self
+            .get_full_server_details(game_id)
+            .await?
+            .rsp_info()?
+            .admin_list
+            .clone())
+    }
+}
@@ -13,3 +13,6 @@ pub const IDENTITY_HOST: &str =
 
 // get_players_by_game_id
 pub const GAMETOOLS: &str = "https://api.gametools.network/bf1/players/";
+
+// get_player_stats
+pub const GAMETOOLS_STATS: &str = "https://api.gametools.network/bf1/all/";
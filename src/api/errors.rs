@@ -21,6 +21,33 @@ impl<T> ApiResultExt<T> for Result<T, BF1ApiError> {
     }
 }
 
+impl BF1ApiError {
+    // Errors worth retrying, anything else will fail the same way again
+    pub fn is_transient(&self) -> bool {
+        match &self.source {
+            BF1ApiSubError::RequestError(err) => {
+                err.is_timeout()
+                    || err.is_connect()
+                    || err
+                        .status()
+                        .is_some_and(|status| status.is_server_error() || status.as_u16() == 429)
+            }
+            BF1ApiSubError::RpcError {
+                code: RpcErrorCode::InternalError,
+                ..
+            } => true,
+            _ => false,
+        }
+    }
+
+    pub fn rpc_code(&self) -> Option<RpcErrorCode> {
+        match &self.source {
+            BF1ApiSubError::RpcError { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
 impl From<BF1ApiSubError> for BF1ApiError {
     fn from(err: BF1ApiSubError) -> Self {
         Self {
@@ -50,11 +77,67 @@ impl Error for BF1ApiError {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcErrorCode {
+    InvalidSession,
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    InternalError,
+    ServerFull,
+    ServerNotFound,
+    InvalidPlayer,
+    PlayerIsAdmin,
+    ServerNotRunning,
+    NoPermission,
+    Other(i64),
+}
+
+impl From<i64> for RpcErrorCode {
+    fn from(code: i64) -> Self {
+        match code {
+            -32501 => RpcErrorCode::InvalidSession,
+            -32600 => RpcErrorCode::InvalidRequest,
+            -32601 => RpcErrorCode::MethodNotFound,
+            -32602 => RpcErrorCode::InvalidParams,
+            -32603 => RpcErrorCode::InternalError,
+            -32850 => RpcErrorCode::ServerFull,
+            -32851 => RpcErrorCode::ServerNotFound,
+            -32856 => RpcErrorCode::InvalidPlayer,
+            -32857 => RpcErrorCode::PlayerIsAdmin,
+            -32858 => RpcErrorCode::ServerNotRunning,
+            -35160 => RpcErrorCode::NoPermission,
+            code => RpcErrorCode::Other(code),
+        }
+    }
+}
+
+impl Display for RpcErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcErrorCode::InvalidSession => write!(f, "Session is invalid or expired"),
+            RpcErrorCode::InvalidRequest => write!(f, "Invalid request"),
+            RpcErrorCode::MethodNotFound => write!(f, "Method not found"),
+            RpcErrorCode::InvalidParams => write!(f, "Invalid params"),
+            RpcErrorCode::InternalError => write!(f, "Internal error"),
+            RpcErrorCode::ServerFull => write!(f, "Server list is full or entry already exists"),
+            RpcErrorCode::ServerNotFound => write!(f, "Server not found or expired"),
+            RpcErrorCode::InvalidPlayer => write!(f, "Invalid player"),
+            RpcErrorCode::PlayerIsAdmin => write!(f, "Can't act on an admin"),
+            RpcErrorCode::ServerNotRunning => write!(f, "Server is not running"),
+            RpcErrorCode::NoPermission => write!(f, "No permission"),
+            RpcErrorCode::Other(code) => write!(f, "Unknown error code {}", code),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum BF1ApiSubError {
     RequestError(reqwest::Error),
     ResponseError(String),
     JsonError(String),
+    RpcError { code: RpcErrorCode, message: String },
+    ServerMatchError(String),
     VarError { var: String, err: VarError },
     EnvError(String),
 }
@@ -77,6 +160,12 @@ impl Display for BF1ApiSubError {
             BF1ApiSubError::JsonError(err) => {
                 write!(f, "Json Error: {}", err)
             }
+            BF1ApiSubError::RpcError { code, message } => {
+                write!(f, "RPC Error: {} ({})", code, message)
+            }
+            BF1ApiSubError::ServerMatchError(err) => {
+                write!(f, "Server selection error: {}", err)
+            }
         }
     }
 }
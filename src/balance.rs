@@ -0,0 +1,81 @@
+use crate::api::bf1api::models::ServerPlayer;
+use crate::api::bf1api::server::ServerDetails;
+use crate::api::bf1api::BF1Api;
+use crate::config::ServerConfig;
+use crate::console::log;
+use crate::discord::announce_team_imbalance;
+use std::time::Instant;
+
+#[derive(Default)]
+pub struct BalanceMonitor {
+    imbalanced_since: Option<Instant>,
+    alerted: bool,
+}
+
+impl BalanceMonitor {
+    // Called after every roster refresh
+    pub async fn check(&mut self, api: &BF1Api, config: &ServerConfig, server: &ServerDetails) {
+        let Some(team_balance) = &config.team_balance else {
+            return;
+        };
+
+        let (team1_count, team2_count) = (server.team1.len(), server.team2.len());
+        if team1_count.abs_diff(team2_count) <= team_balance.threshold {
+            self.imbalanced_since = None;
+            self.alerted = false;
+            return;
+        }
+
+        let imbalanced_for = self
+            .imbalanced_since
+            .get_or_insert(Instant::now())
+            .elapsed();
+        if imbalanced_for < team_balance.duration || self.alerted {
+            return;
+        }
+
+        let moved = match team_balance.auto_move {
+            true => self.rebalance(api, server).await,
+            false => vec![],
+        };
+
+        if let Err(err) =
+            announce_team_imbalance(&config.monitoring_webhook, server, imbalanced_for, &moved)
+                .await
+        {
+            log(&err);
+        }
+
+        // Give the roster a chance to catch up with the moves before trying again
+        if moved.is_empty() {
+            self.alerted = true;
+        } else {
+            self.imbalanced_since = None;
+        }
+    }
+
+    // Moves the most recently joined players off the bigger team, returns their names
+    async fn rebalance(&self, api: &BF1Api, server: &ServerDetails) -> Vec<String> {
+        let (bigger_team, to_team_id) = if server.team1.len() > server.team2.len() {
+            (&server.team1, 2)
+        } else {
+            (&server.team2, 1)
+        };
+        let to_move = server.team1.len().abs_diff(server.team2.len()) / 2;
+
+        let mut players: Vec<(&String, &ServerPlayer)> = bigger_team.iter().collect();
+        players.sort_by_key(|(_, player)| std::cmp::Reverse(player.join_time));
+
+        let mut moved = vec![];
+        for (name, player) in players.into_iter().take(to_move) {
+            match api
+                .move_player(server.game_id.clone(), player.player_id, to_team_id)
+                .await
+            {
+                Ok(_) => moved.push(name.clone()),
+                Err(err) => log(&err),
+            }
+        }
+        moved
+    }
+}
@@ -0,0 +1,317 @@
+use crate::api::bf1api::models::Persona;
+use crate::api::bf1api::BF1Api;
+use crate::config::{Config, ServerConfig};
+use crate::errors::KickbotError;
+use crate::history::{HistoryStore, KickEntry};
+use crate::rotation::switch_map;
+use crate::statistics::EnforcementReport;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use std::fs::File;
+use std::io;
+use std::io::{ErrorKind, Write};
+
+const COMMANDS: [&str; 6] = ["vip", "admins", "map", "config", "history", "stats"];
+
+const USAGE: &str = "Usage: [--server <name>] <command>
+  vip list
+  vip add <player name or persona id>
+  vip remove <player name or persona id>
+  admins list
+  map list
+  map set <rotation index>
+  config check
+  history player <player name or persona id>
+  history top [count]
+  history range <from YYYY-MM-DD> <to YYYY-MM-DD>
+  history pardon <kick id>...
+  history remove <kick id>...
+  history export <json|csv> [file]
+  stats [days] [text|json]";
+
+pub fn is_command(arg: &str) -> bool {
+    arg == "--server" || COMMANDS.contains(&arg)
+}
+
+fn usage_error() -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, USAGE)
+}
+
+fn print_personas(personas: &Vec<Persona>) {
+    for persona in personas {
+        println!("{} ({})", persona.display_name, persona.persona_id);
+    }
+    println!("{} total", personas.len());
+}
+
+async fn resolve_player(api: &BF1Api, player: Option<&String>) -> io::Result<u64> {
+    let player = player.ok_or_else(usage_error)?;
+    if let Ok(persona_id) = player.parse::<u64>() {
+        return Ok(persona_id);
+    }
+    Ok(api.get_player_persona_by_name(player).await?)
+}
+
+fn print_kicks(entries: &[KickEntry]) {
+    for entry in entries {
+        let persona_id = entry
+            .persona_id
+            .map(|id| id.to_string())
+            .unwrap_or("unresolved".to_string());
+        let pardoned = match entry.pardoned_at {
+            Some(pardoned_at) => format!(" (pardoned {})", pardoned_at.format("%Y-%m-%d")),
+            None => String::new(),
+        };
+        let unconfirmed = match entry.confirmed {
+            Some(false) => " (not confirmed)",
+            _ => "",
+        };
+        println!(
+            "#{} {} {} ({}) {} {} \"{}\" {}{}{}",
+            entry.id,
+            entry.kicked_at.format("%Y-%m-%d %H:%M"),
+            entry.player_name,
+            persona_id,
+            entry.action.as_str(),
+            entry.rule,
+            entry.reason,
+            entry.server,
+            pardoned,
+            unconfirmed
+        );
+    }
+    println!("{} total", entries.len());
+}
+
+fn parse_date(date: Option<&String>) -> io::Result<DateTime<Utc>> {
+    let date = date.ok_or_else(usage_error)?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|err| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Bad date {}, {}", date, err),
+        )
+    })?;
+    Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+}
+
+fn parse_kick_ids(ids: &[String]) -> io::Result<Vec<i64>> {
+    if ids.is_empty() {
+        return Err(usage_error());
+    }
+    ids.iter()
+        .map(|id| {
+            id.trim_start_matches('#')
+                .parse::<i64>()
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("Bad kick id {}", id)))
+        })
+        .collect()
+}
+
+fn export(entries: &[KickEntry], format: &str, path: Option<&String>) -> io::Result<()> {
+    let writer: Box<dyn Write> = match path {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    match format {
+        "json" => serde_json::to_writer_pretty(writer, entries).map_err(KickbotError::from)?,
+        "csv" => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for entry in entries {
+                csv_writer.serialize(entry).map_err(KickbotError::from)?;
+            }
+            csv_writer.flush()?;
+        }
+        _ => return Err(usage_error()),
+    }
+    if let Some(path) = path {
+        println!("Exported {} kicks to {}", entries.len(), path);
+    }
+    Ok(())
+}
+
+// Works on the history store alone, so it doesn't need the config or the API
+fn run_history(args: &[String]) -> io::Result<()> {
+    let store = HistoryStore::open_shared()?;
+
+    match args.first().map(String::as_str) {
+        Some("player") => {
+            let player = args.get(1).ok_or_else(usage_error)?;
+            let persona_id = match player.parse::<u64>() {
+                Ok(persona_id) => Some(persona_id),
+                Err(_) => store.persona_for_name(player)?,
+            };
+            let entries = match persona_id {
+                Some(persona_id) => {
+                    let names = store.names_for_persona(persona_id)?;
+                    if !names.is_empty() {
+                        println!("{} is known as {}", persona_id, names.join(", "));
+                    }
+                    store.player_kicks(persona_id)?
+                }
+                // Only kicks from before history was keyed by persona
+                None => store.name_kicks(player)?,
+            };
+            print_kicks(&entries);
+        }
+        Some("top") => {
+            let count = match args.get(1) {
+                Some(count) => count.parse::<u64>().map_err(|_| usage_error())?,
+                None => 10,
+            };
+            for (rank, (persona_id, name, kicks)) in store.top_offenders(count)?.iter().enumerate()
+            {
+                let persona_id = persona_id
+                    .map(|id| id.to_string())
+                    .unwrap_or("unresolved".to_string());
+                println!("{}. {} ({}) {} kicks", rank + 1, name, persona_id, kicks);
+            }
+        }
+        Some("range") => {
+            let from = parse_date(args.get(1))?;
+            // Inclusive of the whole last day
+            let to = parse_date(args.get(2))? + TimeDelta::days(1);
+            print_kicks(&store.kicks_between(from, to)?);
+        }
+        Some(action @ ("pardon" | "remove")) => {
+            for id in parse_kick_ids(&args[1..])? {
+                let (changed, done) = match action {
+                    "pardon" => (store.pardon(id)?, "Pardoned"),
+                    _ => (store.remove(id)?, "Removed"),
+                };
+                match changed {
+                    true => println!("{} kick #{}", done, id),
+                    false => println!("No kick #{} to {}", id, action),
+                }
+            }
+        }
+        Some("export") => {
+            let format = args.get(1).ok_or_else(usage_error)?;
+            export(&store.all_kicks()?, format, args.get(2))?;
+        }
+        _ => return Err(usage_error()),
+    }
+
+    Ok(())
+}
+
+// Report over the last few days of the history store, a week by default
+fn run_stats(args: &[String]) -> io::Result<()> {
+    let days = match args.first() {
+        Some(days) => days.parse::<u32>().map_err(|_| usage_error())?,
+        None => 7,
+    };
+    let to = Utc::now();
+    let from = TimeDelta::try_days(days as i64)
+        .and_then(|period| to.checked_sub_signed(period))
+        .ok_or_else(usage_error)?;
+    let store = HistoryStore::open_shared()?;
+    let report = EnforcementReport::build(&store.kicks_between(from, to)?, from, to);
+
+    match args.get(1).map(String::as_str) {
+        Some("text") | None => println!("{}", report.to_text()),
+        Some("json") => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(KickbotError::from)?
+        ),
+        _ => return Err(usage_error()),
+    }
+    Ok(())
+}
+
+// Defaults to the spectated server when --server isn't given
+fn select_server<'a>(
+    config: &'a Config,
+    server_name: Option<&String>,
+) -> io::Result<&'a ServerConfig> {
+    match server_name {
+        Some(name) => config.server_by_name(name).ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("No server named {} in config", name),
+            )
+        }),
+        None => Ok(config.spectated_server()),
+    }
+}
+
+// Runs a one-off command against the server instead of starting the bot
+pub async fn run(args: &[String]) -> io::Result<()> {
+    let (server_name, args) = match args {
+        [flag, name, rest @ ..] if flag == "--server" => (Some(name), rest),
+        _ => (None, args),
+    };
+    if args.is_empty() || !COMMANDS.contains(&args[0].as_str()) {
+        return Err(usage_error());
+    }
+
+    match args[0].as_str() {
+        "history" => return run_history(&args[1..]),
+        "stats" => return run_stats(&args[1..]),
+        _ => {}
+    }
+
+    let config = Config::read_config("config.json")?;
+    let server_config = select_server(&config, server_name)?;
+
+    // Doesn't need the API or Discord, reading the config is the check
+    if let ("config", Some("check") | None) = (args[0].as_str(), args.get(1).map(String::as_str)) {
+        if !server_config.spectate {
+            println!(
+                "Config OK, {} is only monitored, kicks happen on {}",
+                server_config.name,
+                config.spectated_server().name
+            );
+            return Ok(());
+        }
+        println!("Config OK, kick reasons for {}:", server_config.name);
+        for line in server_config.kick_reasons.preview() {
+            println!("  {}", line);
+        }
+        return Ok(());
+    }
+    let api = BF1Api::new(config.api_policy.clone(), config.roster_policy.clone()).await?;
+    let server = api
+        .get_server(&server_config.name, server_config.guid.as_deref())
+        .await?;
+
+    match (args[0].as_str(), args.get(1).map(String::as_str)) {
+        ("vip", Some("list")) => print_personas(&api.get_vip_list(&server.game_id).await?),
+        ("vip", Some("add")) => {
+            let persona_id = resolve_player(&api, args.get(2)).await?;
+            let server_id = api.get_server_id(&server.game_id).await?;
+            api.add_vip(server_id, persona_id).await?;
+            println!("Added {} as VIP on {}", persona_id, server.name);
+        }
+        ("vip", Some("remove")) => {
+            let persona_id = resolve_player(&api, args.get(2)).await?;
+            let server_id = api.get_server_id(&server.game_id).await?;
+            api.remove_vip(server_id, persona_id).await?;
+            println!("Removed {} as VIP on {}", persona_id, server.name);
+        }
+        ("admins", Some("list") | None) => {
+            print_personas(&api.get_admin_list(&server.game_id).await?)
+        }
+        ("map", Some("list") | None) => {
+            for (index, map) in server.rotation.iter().enumerate() {
+                let current = match server.rotation_index == Some(index) {
+                    true => " <- current",
+                    false => "",
+                };
+                println!(
+                    "{}: {} ({}){}",
+                    index, map.map_pretty_name, map.mode_pretty_name, current
+                );
+            }
+        }
+        ("map", Some("set")) => {
+            let level_index = args
+                .get(2)
+                .and_then(|index| index.parse::<usize>().ok())
+                .ok_or_else(usage_error)?;
+            switch_map(&api, &server, level_index).await?;
+            println!("Switched {} to rotation index {}", server.name, level_index);
+        }
+        _ => return Err(usage_error()),
+    }
+
+    Ok(())
+}
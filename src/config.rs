@@ -1,18 +1,28 @@
+use crate::api::bf1api::models::{PlayerStats, ServerPlayer};
+use crate::api::bf1api::request::RequestPolicy;
+use crate::api::bf1api::roster::RosterPolicy;
+use crate::api::bf1api::server::ServerDetails;
 use crate::console::log;
+use crate::cycle::RoundState;
 use crate::discord::{announce_player_multiple_kicks, DiscordWebhook};
 use crate::errors::KickbotError;
-use crate::errors::KickbotError::{IOError, JsonError};
+use crate::errors::KickbotError::JsonError;
+use crate::history::{HistoryPolicy, HistoryStore, KickAction, KickEntry, RetentionAction};
+use crate::reason::{validate_template, KickReasons, ReasonTemplate, MAX_REASON_LENGTH};
 use crate::recognition::enhance::RGB;
 use crate::recognition::model::WeaponClasses;
-use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
 use gestalt_ratio::gestalt_ratio;
 use opencv::core::Rect;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter};
 use std::path::Path;
 use std::time::Duration;
+use tokio::sync::Mutex;
 
 pub fn dates_to_csv_string(dates: &Vec<DateTime<Utc>>) -> Vec<String> {
     dates
@@ -21,25 +31,6 @@ pub fn dates_to_csv_string(dates: &Vec<DateTime<Utc>>) -> Vec<String> {
         .collect()
 }
 
-fn get_total_kicks(kick_record: &HashMap<String, Vec<DateTime<Utc>>>) -> u64 {
-    kick_record
-        .iter()
-        .fold(0, |acc, (_, dates)| acc + dates.len() as u64)
-}
-
-pub fn weapon_kick_records_to_csv_strings(
-    kick_records: &HashMap<String, Vec<DateTime<Utc>>>,
-) -> Vec<String> {
-    kick_records
-        .iter()
-        .flat_map(|(name, dates)| {
-            let mut entries = vec![name.clone()];
-            entries.extend(dates_to_csv_string(dates));
-            entries
-        })
-        .collect()
-}
-
 #[derive(Debug)]
 pub struct Vehicle {
     pub pretty_name: String,
@@ -59,139 +50,409 @@ pub struct Weapon {
     pub names: Vec<String>,
 }
 
-pub type PlayerKickHistoryRecord = HashMap<String, HashMap<String, Vec<DateTime<Utc>>>>;
+// The name kicks are recorded under, None for kicks that aren't recorded
+pub fn rule_name(config: &Config, weapon_type: &WeaponClasses) -> Option<String> {
+    match weapon_type {
+        WeaponClasses::AllowedPrimaryGuns => None,
+        WeaponClasses::HeavyBomber | WeaponClasses::HMG | WeaponClasses::LMG => config
+            .banned_vehicles
+            .get(weapon_type)
+            .map(|vehicle| vehicle.pretty_name.clone()),
+        WeaponClasses::SMG08 => Some("smg08".to_string()),
+    }
+}
 
-static CSV_FILE_NAME: &str = "kick_history.csv";
+pub async fn add_to_player_kick_record(
+    kick_record: &Mutex<HistoryStore>,
+    kicks_to_ping: u64,
+    history_policy: &HistoryPolicy,
+    entry: KickEntry,
+    kick_webhook: &DiscordWebhook,
+    stats: Option<&PlayerStats>,
+) {
+    // The store isn't Sync, so it's only locked around the queries and not the announcement
+    let announcement = {
+        let kick_record = kick_record.lock().await;
+        let result = kick_record.record_kick(&entry).and_then(|_| {
+            let Some(persona_id) = entry.persona_id else {
+                return Ok(None);
+            };
+            // Warnings only count towards escalation, the ping is about kicks
+            if entry.action == KickAction::Warn {
+                return Ok(None);
+            }
+            // Only offences that haven't decayed count towards the ping
+            let active_offences =
+                kick_record.count_kicks(persona_id, history_policy.active_since())?;
+            if active_offences > 1 && active_offences % kicks_to_ping == 0 {
+                let lifetime_offences = kick_record.count_lifetime_kicks(persona_id)?;
+                let kicks_by_rule = kick_record.kicks_by_rule(persona_id)?;
+                let aliases = kick_record.names_for_persona(persona_id)?;
+                return Ok(Some((
+                    active_offences,
+                    lifetime_offences,
+                    kicks_by_rule,
+                    aliases,
+                )));
+            }
+            Ok(None)
+        });
+        match result {
+            Ok(announcement) => announcement,
+            Err(err) => {
+                log(&err);
+                return;
+            }
+        }
+    };
 
-fn get_csv_path() -> Result<&'static str, KickbotError> {
-    if let Ok(false) = std::fs::exists(CSV_FILE_NAME) {
-        log(&IOError(format!(
-            "File {CSV_FILE_NAME} does not exist, creating"
-        )));
-        File::create_new(CSV_FILE_NAME)?;
+    if let Some((active_offences, lifetime_offences, kicks_by_rule, aliases)) = announcement {
+        if let Err(err) = announce_player_multiple_kicks(
+            kick_webhook,
+            entry.player_name.as_str(),
+            entry
+                .persona_id
+                .map(|id| id.to_string())
+                .unwrap_or_default()
+                .as_str(),
+            active_offences,
+            lifetime_offences,
+            &kicks_by_rule,
+            &aliases,
+            stats,
+        )
+        .await
+        {
+            log(&err);
+        }
     }
-    Ok(CSV_FILE_NAME)
 }
 
-pub fn load_kick_history_record() -> Result<PlayerKickHistoryRecord, KickbotError> {
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_path(get_csv_path()?)?;
+static SHADOW_CSV_FILE_NAME: &str = "shadow_history.csv";
 
-    let mut player_kick_history_records = PlayerKickHistoryRecord::new();
+// One row per would-be kick so they can be compared against what an admin would have done
+pub fn add_to_shadow_history(
+    server_name: &str,
+    player_name: &str,
+    persona_id: &str,
+    banned_weapon: &str,
+    reason: &str,
+) -> Result<(), KickbotError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(SHADOW_CSV_FILE_NAME)?;
+    let mut csv_writer = csv::Writer::from_writer(file);
+    csv_writer.write_record([
+        Utc::now().format("%Y-%m-%d %H:%M").to_string().as_str(),
+        server_name,
+        player_name,
+        persona_id,
+        banned_weapon,
+        reason,
+    ])?;
+    csv_writer.flush()?;
+    Ok(())
+}
 
-    for result in csv_reader.records() {
-        if let Ok(record) = result {
-            let mut iter = record.iter();
-            let player_name = match iter.next() {
-                None => {
-                    continue;
-                }
-                Some(player_name) => player_name,
-            }
-            .to_string();
-
-            let mut weapon_records: HashMap<String, Vec<DateTime<Utc>>> = HashMap::new();
-
-            let mut current_weapon = String::new();
-            for entry in iter {
-                if let Ok(date) = NaiveDateTime::parse_from_str(entry, "%Y-%m-%d %H:%M") {
-                    if let Some(dates) = weapon_records.get_mut(&current_weapon) {
-                        dates.push(DateTime::<Utc>::from_naive_utc_and_offset(date, Utc));
-                    } else {
-                        log(&KickbotError::IOError(
-                            "Expected weapon name not date".to_string(),
-                        ));
-                        continue;
-                    }
-                } else {
-                    current_weapon = entry.to_string();
-                    weapon_records.insert(current_weapon.clone(), vec![]);
-                }
-            }
-            player_kick_history_records.insert(player_name, weapon_records);
-        }
+#[derive(Debug)]
+pub struct ShadowMode {
+    // Every rule is shadowed when set, otherwise only the listed ones
+    pub all_rules: bool,
+    pub rules: HashSet<String>,
+    pub webhook: DiscordWebhook,
+}
+
+impl ShadowMode {
+    pub fn applies_to(&self, banned_weapon: &str) -> bool {
+        self.all_rules || self.rules.contains(banned_weapon)
     }
+}
 
-    Ok(player_kick_history_records)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EscalationAction {
+    // The game has no way to message a player, so a warning is only logged and announced
+    Warn,
+    Kick,
+    TempBan(TimeDelta),
+    Ban,
 }
 
-pub async fn add_to_player_kick_record(
-    kick_record: &mut PlayerKickHistoryRecord,
-    config: &Config,
-    player_name: String,
-    weapon_type: WeaponClasses,
-    kick_webhook: &DiscordWebhook,
-    player_pid: &str,
-) {
-    let weapon_string = match weapon_type {
-        WeaponClasses::AllowedPrimaryGuns => {
-            return;
-        }
-        WeaponClasses::HeavyBomber | WeaponClasses::HMG | WeaponClasses::LMG => {
-            match config.banned_vehicles.get(&weapon_type) {
-                None => {
-                    return;
-                }
-                Some(vehicle) => vehicle.pretty_name.clone(),
-            }
+impl From<EscalationAction> for KickAction {
+    fn from(action: EscalationAction) -> Self {
+        match action {
+            EscalationAction::Warn => KickAction::Warn,
+            EscalationAction::Kick => KickAction::Kick,
+            EscalationAction::TempBan(_) => KickAction::TempBan,
+            EscalationAction::Ban => KickAction::Ban,
         }
-        WeaponClasses::SMG08 => "smg08".to_string(),
-    };
+    }
+}
 
-    let date = Utc::now();
+#[derive(Debug)]
+pub struct EscalationStep {
+    // Applies from this offence until the next step
+    pub offences: u64,
+    pub action: EscalationAction,
+    // Overrides kick_reasons for this step
+    pub reason: Option<String>,
+}
 
-    match kick_record.entry(player_name.clone()) {
-        Entry::Occupied(mut value) => {
-            let mut records = value.get_mut();
-            match records.entry(weapon_string.clone()) {
-                Entry::Occupied(mut dates) => dates.get_mut().push(date),
-                Entry::Vacant(_) => {
-                    records.insert(weapon_string, vec![date]);
-                }
-            }
-            let total_offences = get_total_kicks(value.get());
-            if total_offences % config.kicks_to_ping == 0 {
-                if let Err(err) = announce_player_multiple_kicks(
-                    kick_webhook,
-                    player_name.as_str(),
-                    player_pid,
-                    total_offences,
-                    &value.get(),
-                )
-                .await
-                {
-                    log(&err);
-                }
-            };
-        }
-        Entry::Vacant(_) => {
-            kick_record.insert(player_name, HashMap::from([(weapon_string, vec![date])]));
+#[derive(Debug)]
+pub struct EscalationLadder {
+    // Offences older than this don't count, None counts every offence
+    pub window: Option<TimeDelta>,
+    // Sorted by offences, the first step is always for the first offence
+    pub steps: Vec<EscalationStep>,
+}
+
+impl Default for EscalationLadder {
+    fn default() -> Self {
+        EscalationLadder {
+            window: None,
+            steps: vec![EscalationStep {
+                offences: 1,
+                action: EscalationAction::Kick,
+                reason: None,
+            }],
         }
+    }
+}
+
+impl EscalationLadder {
+    // Includes the offence being handled. The ladder's own window wins over the offence decay
+    pub fn count_offences(
+        &self,
+        kick_record: &HistoryStore,
+        persona_id: u64,
+        offence_decay: Option<TimeDelta>,
+    ) -> u64 {
+        let since = self
+            .window
+            .or(offence_decay)
+            .map(|window| Utc::now() - window);
+        let previous = kick_record
+            .count_offences(persona_id, since)
+            .unwrap_or_else(|err| {
+                log(&err);
+                0
+            });
+        previous + 1
+    }
+
+    pub fn step_for(&self, offences: u64) -> &EscalationStep {
+        self.steps
+            .iter()
+            .rev()
+            .find(|step| step.offences <= offences)
+            .unwrap_or(&self.steps[0])
+    }
+}
+
+// A missing file is the same as an empty one, a broken one is logged and ignored
+fn load_json_file<T: DeserializeOwned + Default>(file_name: &str) -> T {
+    let Ok(reader) = File::open(file_name) else {
+        return T::default();
     };
+    serde_json::from_reader(BufReader::new(reader)).unwrap_or_else(|err| {
+        log(&JsonError(format!(
+            "File {file_name} is not valid JSON, ignoring: {err}"
+        )));
+        T::default()
+    })
+}
+
+// Written next to the file then renamed over it, so being killed mid write leaves the old one
+fn save_json_file<T: Serialize>(file_name: &str, value: &T) -> Result<(), KickbotError> {
+    let temp_file_name = format!("{file_name}.tmp");
+    let mut writer = BufWriter::new(File::create(&temp_file_name)?);
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+    std::fs::rename(temp_file_name, file_name)?;
+    Ok(())
+}
+
+static ROUND_STATE_FILE_NAME: &str = "round_state.json";
+
+// Guards the round state file, every server's refresh loop writes to it
+static ROUND_STATE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// Server name -> its last saved round state
+fn load_round_states() -> HashMap<String, RoundState> {
+    load_json_file(ROUND_STATE_FILE_NAME)
+}
+
+pub fn load_round_state(server_name: &str) -> Option<RoundState> {
+    let _guard = ROUND_STATE_LOCK.lock().unwrap();
+    load_round_states().remove(server_name)
+}
+
+pub fn save_round_state(server_name: &str, round_state: RoundState) -> Result<(), KickbotError> {
+    let _guard = ROUND_STATE_LOCK.lock().unwrap();
+    let mut round_states = load_round_states();
+    round_states.insert(server_name.to_string(), round_state);
+    save_json_file(ROUND_STATE_FILE_NAME, &round_states)
+}
+
+static TEMP_BANS_FILE_NAME: &str = "temp_bans.json";
+
+// Guards the temp bans file, every server's refresh loop reads and writes it
+static TEMP_BANS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TempBan {
+    pub server_id: u64,
+    pub persona_id: u64,
+    // Unix timestamp
+    pub until: i64,
 }
 
-pub fn save_kick_record(kick_record: &PlayerKickHistoryRecord) -> Result<(), KickbotError> {
-    let mut csv_writer = csv::Writer::from_path(get_csv_path()?)?;
+fn load_temp_bans() -> Vec<TempBan> {
+    load_json_file(TEMP_BANS_FILE_NAME)
+}
 
-    for (player_name, kick_record) in kick_record {
-        let mut record = vec![player_name.clone()];
-        let mut record_strings = weapon_kick_records_to_csv_strings(kick_record);
+fn save_temp_bans(temp_bans: &Vec<TempBan>) -> Result<(), KickbotError> {
+    save_json_file(TEMP_BANS_FILE_NAME, temp_bans)
+}
 
-        record.append(&mut record_strings);
+pub fn add_temp_ban(temp_ban: TempBan) -> Result<(), KickbotError> {
+    let _guard = TEMP_BANS_LOCK.lock().unwrap();
+    let mut temp_bans = load_temp_bans();
+    temp_bans.retain(|saved| {
+        saved.server_id != temp_ban.server_id || saved.persona_id != temp_ban.persona_id
+    });
+    temp_bans.push(temp_ban);
+    save_temp_bans(&temp_bans)
+}
 
-        csv_writer.write_record(&record)?;
+// Removes and returns the server's temp bans that have run out
+pub fn take_expired_temp_bans(server_id: u64) -> Result<Vec<TempBan>, KickbotError> {
+    let _guard = TEMP_BANS_LOCK.lock().unwrap();
+    let now = Utc::now().timestamp();
+    let (expired, remaining): (Vec<TempBan>, Vec<TempBan>) = load_temp_bans()
+        .into_iter()
+        .partition(|temp_ban| temp_ban.server_id == server_id && temp_ban.until <= now);
+    if !expired.is_empty() {
+        save_temp_bans(&remaining)?;
     }
-    Ok(())
+    Ok(expired)
 }
 
 #[derive(Debug)]
-pub struct Config {
-    pub bf1_path: String,
+pub struct KickVerification {
+    // Roster checks to wait for the player to leave before the kick counts as unconfirmed
+    pub checks: u32,
+    pub interval: Duration,
+    pub retries: u32,
+}
+
+#[derive(Debug)]
+pub struct TeamBalance {
+    pub threshold: usize,
+    pub duration: Duration,
+    pub auto_move: bool,
+}
+
+#[derive(Debug)]
+pub struct RotationControl {
+    pub max_map_duration: Option<Duration>,
+    pub seeding_index: Option<usize>,
+    pub seeding_below_players: Option<u64>,
+    // How long the player count has to stay below the threshold before switching
+    pub seeding_duration: Duration,
+}
+
+#[derive(Debug)]
+pub struct Immunity {
+    // Merge in the server's own admin/VIP lists
+    pub admins: bool,
+    pub vips: bool,
+    pub persona_ids: HashSet<u64>,
+    // Player names without the platoon tag, * matches anything and ? a single character
+    pub name_patterns: Vec<String>,
+    // Without the brackets
+    pub platoons: HashSet<String>,
+}
+
+impl Immunity {
+    // Why the player can't be kicked, None if they can
+    pub fn reason(&self, server: &ServerDetails, player: &ServerPlayer) -> Option<String> {
+        if self.admins && server.is_admin(player.player_id) {
+            return Some("server admin".to_string());
+        }
+        if self.vips && server.is_vip(player.player_id) {
+            return Some("server VIP".to_string());
+        }
+        if self.persona_ids.contains(&player.player_id) {
+            return Some(format!("persona {} is immune", player.player_id));
+        }
+        if !player.platoon.is_empty() && self.platoons.contains(&player.platoon.to_lowercase()) {
+            return Some(format!("platoon [{}] is immune", player.platoon));
+        }
+        self.name_patterns
+            .iter()
+            .find(|pattern| matches_pattern(pattern, &player.name.to_lowercase()))
+            .map(|pattern| format!("name matches {pattern}"))
+    }
+}
+
+// Wildcard match, patterns are lowercased when the config is read
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Rules and webhooks for one monitored server, anything not set on the server
+// falls back to the top level of the config. Exactly one server is spectated,
+// the others only get roster, balance and rotation monitoring
+#[derive(Debug)]
+pub struct ServerConfig {
+    pub name: String,
+    pub guid: Option<String>,
+    pub spectate: bool,
     pub kicks_to_ping: u64,
     pub min_players_for_kick: u64,
     pub kick_webhook: DiscordWebhook,
     pub monitoring_webhook: DiscordWebhook,
+    pub escalation: EscalationLadder,
+    pub immunity: Immunity,
+    pub team_balance: Option<TeamBalance>,
+    pub kick_verification: Option<KickVerification>,
+    pub kick_reasons: KickReasons,
+    pub shadow: Option<ShadowMode>,
+    pub rotation_control: Option<RotationControl>,
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub bf1_path: String,
+    pub monitoring_webhook: DiscordWebhook,
     pub player_similar_name_probability: f64,
     pub weapon_similar_name_probability: f64,
     pub save_screenshots: bool,
@@ -208,6 +469,12 @@ pub struct Config {
     pub banned_vehicles: HashMap<WeaponClasses, Vehicle>,
     pub banned_gadgets: Vec<Gadget>,
     pub banned_weapon: Weapon,
+    pub api_policy: RequestPolicy,
+    pub roster_policy: RosterPolicy,
+    pub history_policy: HistoryPolicy,
+    // Posts a summary of the week's kicks to the monitoring webhook
+    pub weekly_summary: bool,
+    pub servers: Vec<ServerConfig>,
 }
 
 trait Subfield<T> {
@@ -259,6 +526,55 @@ fn deserialize_primitive<T>(
     parse_primitive(field_object, cast_func).err_parent(field)
 }
 
+fn deserialize_primitive_or<T>(
+    object: &Value,
+    field: &str,
+    cast_func: fn(&Value) -> Option<T>,
+    default: T,
+) -> Result<T, KickbotError> {
+    match object.get(field) {
+        None => Ok(default),
+        Some(field_object) => parse_primitive(field_object, cast_func).err_parent(field),
+    }
+}
+
+fn deserialize_optional<T>(
+    object: &Value,
+    field: &str,
+    cast_func: fn(&Value) -> Option<T>,
+) -> Result<Option<T>, KickbotError> {
+    match object.get(field) {
+        None => Ok(None),
+        Some(field_object) => parse_primitive(field_object, cast_func)
+            .err_parent(field)
+            .map(Some),
+    }
+}
+
+// Durations in the config are seconds, anything negative, NaN or too large is rejected
+fn to_duration(seconds: f64, field: &str) -> Result<Duration, KickbotError> {
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|err| JsonError(format!("Invalid duration {seconds} for {field}, {err}")))
+}
+
+// Windows and bans in days or hours, they have to be positive and fit either side of a date
+fn to_time_delta(
+    amount: i64,
+    field: &str,
+    to_delta: fn(i64) -> Option<TimeDelta>,
+) -> Result<TimeDelta, KickbotError> {
+    let now = Utc::now();
+    to_delta(amount)
+        .filter(|delta| {
+            amount > 0
+                && now.checked_add_signed(*delta).is_some()
+                && now.checked_sub_signed(*delta).is_some()
+        })
+        .ok_or(JsonError(format!(
+            "Invalid {field} {amount}, it must be positive and not too large"
+        )))
+}
+
 fn cant_find(field: &str) -> KickbotError {
     KickbotError::JsonError(format!("Couldn't find field {field}"))
 }
@@ -297,8 +613,605 @@ fn to_rect(object: &Value, field: &str) -> Result<Rect, KickbotError> {
     })
 }
 
+// Optional, anything left out uses the defaults
+fn to_request_policy(object: &Value, field: &str) -> Result<RequestPolicy, KickbotError> {
+    let default = RequestPolicy::default();
+    let Some(policy_object) = object.get(field) else {
+        return Ok(default);
+    };
+
+    let seconds = |subfield: &str, default: Duration| -> Result<Duration, KickbotError> {
+        let seconds = deserialize_primitive_or(
+            policy_object,
+            subfield,
+            Value::as_f64,
+            default.as_secs_f64(),
+        )
+        .err_parent(field)?;
+        to_duration(seconds, subfield).err_parent(field)
+    };
+
+    let requests_per_second = deserialize_primitive_or(
+        policy_object,
+        "requests_per_second",
+        Value::as_f64,
+        default.requests_per_second,
+    )
+    .err_parent(field)?;
+    // Also catches NaN
+    if !(requests_per_second > 0.0 && requests_per_second.is_finite()) {
+        return Err(JsonError(format!(
+            "In field {field}, requests_per_second must be above 0"
+        )));
+    }
+    let burst =
+        deserialize_primitive_or(policy_object, "burst", Value::as_u64, default.burst as u64)
+            .err_parent(field)?;
+    if burst == 0 || burst > u32::MAX as u64 {
+        return Err(JsonError(format!(
+            "In field {field}, burst must be between 1 and {}",
+            u32::MAX
+        )));
+    }
+
+    Ok(RequestPolicy {
+        max_retries: deserialize_primitive_or(
+            policy_object,
+            "max_retries",
+            Value::as_u64,
+            default.max_retries as u64,
+        )
+        .err_parent(field)? as u32,
+        base_delay: seconds("base_delay", default.base_delay)?,
+        max_delay: seconds("max_delay", default.max_delay)?,
+        requests_per_second,
+        burst: burst as u32,
+    })
+}
+
+// Optional, gametools is always the roster, this only tunes when the companion API stands in
+fn to_roster_policy(object: &Value, field: &str) -> Result<RosterPolicy, KickbotError> {
+    let default = RosterPolicy::default();
+    let Some(roster_object) = object.get(field) else {
+        return Ok(default);
+    };
+
+    Ok(RosterPolicy {
+        failover_after: deserialize_primitive_or(
+            roster_object,
+            "failover_after",
+            Value::as_u64,
+            default.failover_after as u64,
+        )
+        .err_parent(field)? as u32,
+        retry_primary_after: Duration::from_secs(
+            deserialize_primitive_or(
+                roster_object,
+                "retry_primary_after",
+                Value::as_u64,
+                default.retry_primary_after.as_secs(),
+            )
+            .err_parent(field)?,
+        ),
+    })
+}
+
+// Optional, defaults to every kick counting forever and nothing being archived
+fn to_history_policy(object: &Value, field: &str) -> Result<HistoryPolicy, KickbotError> {
+    let default = HistoryPolicy::default();
+    let Some(history_object) = object.get(field) else {
+        return Ok(default);
+    };
+
+    let retention_action = match history_object.get("retention_action") {
+        None => default.retention_action,
+        Some(_) => {
+            let name = get_str(history_object, "retention_action").err_parent(field)?;
+            RetentionAction::from_name(name).ok_or(JsonError(format!(
+                "Unknown retention action {name} in {field}, expected archive or delete"
+            )))?
+        }
+    };
+
+    Ok(HistoryPolicy {
+        offence_decay: deserialize_optional(history_object, "offence_decay_days", Value::as_i64)
+            .err_parent(field)?
+            .map(|days| to_time_delta(days, "offence_decay_days", TimeDelta::try_days))
+            .transpose()
+            .err_parent(field)?,
+        retention: deserialize_optional(history_object, "retention_days", Value::as_i64)
+            .err_parent(field)?
+            .map(|days| to_time_delta(days, "retention_days", TimeDelta::try_days))
+            .transpose()
+            .err_parent(field)?,
+        retention_action,
+    })
+}
+
+fn to_escalation_action(step_object: &Value) -> Result<EscalationAction, KickbotError> {
+    match get_str(step_object, "action")? {
+        "warn" => Ok(EscalationAction::Warn),
+        "kick" => Ok(EscalationAction::Kick),
+        "temp_ban" => Ok(EscalationAction::TempBan(to_time_delta(
+            deserialize_primitive(step_object, "hours", Value::as_i64)?,
+            "hours",
+            TimeDelta::try_hours,
+        )?)),
+        "ban" => Ok(EscalationAction::Ban),
+        action => Err(JsonError(format!(
+            "Unknown action {action}, expected warn, kick, temp_ban or ban"
+        ))),
+    }
+}
+
+// Optional, without it every offence is a kick
+fn to_escalation(object: &Value) -> Result<EscalationLadder, KickbotError> {
+    let field = "escalation";
+    let Some(escalation_object) = object.get(field) else {
+        return Ok(EscalationLadder::default());
+    };
+
+    let mut steps = vec![];
+    for (idx, step_object) in deserialize(escalation_object, "steps", Value::as_array)
+        .err_parent(field)?
+        .iter()
+        .enumerate()
+    {
+        let parent = format!("steps[{idx}]");
+        let reason = match step_object.get("reason") {
+            None => None,
+            Some(_) => Some(
+                get_str(step_object, "reason")
+                    .err_parent(parent.as_str())
+                    .err_parent(field)?
+                    .to_string(),
+            ),
+        };
+        if let Some(reason) = &reason {
+            validate_template(reason).map_err(JsonError)?;
+        }
+        steps.push(EscalationStep {
+            offences: deserialize_primitive(step_object, "offences", Value::as_u64)
+                .err_parent(parent.as_str())
+                .err_parent(field)?,
+            action: to_escalation_action(step_object)
+                .err_parent(parent.as_str())
+                .err_parent(field)?,
+            reason,
+        });
+    }
+
+    steps.sort_by_key(|step| step.offences);
+    if steps.first().map(|step| step.offences) != Some(1) {
+        return Err(JsonError(format!(
+            "In field {field}, the first step must be for offence 1"
+        )));
+    }
+    // Only the last of two steps for the same offence would ever apply
+    if let Some(steps) = steps
+        .windows(2)
+        .find(|steps| steps[0].offences == steps[1].offences)
+    {
+        return Err(JsonError(format!(
+            "In field {field}, more than one step is for offence {}",
+            steps[0].offences
+        )));
+    }
+
+    Ok(EscalationLadder {
+        window: deserialize_optional(escalation_object, "window_days", Value::as_i64)
+            .err_parent(field)?
+            .map(|days| to_time_delta(days, "window_days", TimeDelta::try_days))
+            .transpose()
+            .err_parent(field)?,
+        steps,
+    })
+}
+
+fn to_lowercase_strings(object: &Value, field: &str) -> Result<Vec<String>, KickbotError> {
+    if object.get(field).is_none() {
+        return Ok(vec![]);
+    }
+    deserialize(object, field, Value::as_array)?
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| {
+            value
+                .as_str()
+                .map(str::to_lowercase)
+                .ok_or(JsonError(format!("Couldn't parse {field}[{idx}] as str")))
+        })
+        .collect()
+}
+
+// Server admins are immune by default
+fn to_immunity(object: &Value) -> Result<Immunity, KickbotError> {
+    let field = "immunity";
+    let Some(immunity_object) = object.get(field) else {
+        return Ok(Immunity {
+            admins: true,
+            vips: false,
+            persona_ids: HashSet::new(),
+            name_patterns: vec![],
+            platoons: HashSet::new(),
+        });
+    };
+
+    let mut persona_ids = HashSet::new();
+    if immunity_object.get("persona_ids").is_some() {
+        for (idx, persona_id) in deserialize(immunity_object, "persona_ids", Value::as_array)
+            .err_parent(field)?
+            .iter()
+            .enumerate()
+        {
+            persona_ids.insert(
+                parse_primitive(persona_id, Value::as_u64)
+                    .err_parent(format!("persona_ids[{idx}]").as_str())
+                    .err_parent(field)?,
+            );
+        }
+    }
+
+    Ok(Immunity {
+        admins: deserialize_primitive_or(immunity_object, "admins", Value::as_bool, true)
+            .err_parent(field)?,
+        vips: deserialize_primitive_or(immunity_object, "vips", Value::as_bool, false)
+            .err_parent(field)?,
+        persona_ids,
+        name_patterns: to_lowercase_strings(immunity_object, "names").err_parent(field)?,
+        platoons: to_lowercase_strings(immunity_object, "platoons")
+            .err_parent(field)?
+            .into_iter()
+            .map(|platoon| platoon.trim_matches(['[', ']']).to_string())
+            .collect(),
+    })
+}
+
+fn to_team_balance(object: &Value, field: &str) -> Result<Option<TeamBalance>, KickbotError> {
+    let Some(balance_object) = object.get(field) else {
+        return Ok(None);
+    };
+
+    Ok(Some(TeamBalance {
+        threshold: deserialize_primitive(balance_object, "threshold", Value::as_u64)
+            .err_parent(field)? as usize,
+        duration: to_duration(
+            deserialize_primitive(balance_object, "duration", Value::as_f64).err_parent(field)?,
+            "duration",
+        )
+        .err_parent(field)?,
+        auto_move: deserialize_primitive_or(balance_object, "auto_move", Value::as_bool, false)
+            .err_parent(field)?,
+    }))
+}
+
+fn to_kick_verification(
+    object: &Value,
+    field: &str,
+) -> Result<Option<KickVerification>, KickbotError> {
+    let Some(verification_object) = object.get(field) else {
+        return Ok(None);
+    };
+
+    let checks = deserialize_primitive_or(verification_object, "checks", Value::as_u64, 3)
+        .err_parent(field)?;
+    // Without a single check every kick would come out unconfirmed
+    if checks == 0 || checks > u32::MAX as u64 {
+        return Err(JsonError(format!(
+            "In field {field}, checks must be between 1 and {}",
+            u32::MAX
+        )));
+    }
+
+    Ok(Some(KickVerification {
+        checks: checks as u32,
+        interval: to_duration(
+            deserialize_primitive_or(verification_object, "interval", Value::as_f64, 10.0)
+                .err_parent(field)?,
+            "interval",
+        )
+        .err_parent(field)?,
+        retries: deserialize_primitive_or(verification_object, "retries", Value::as_u64, 0)
+            .err_parent(field)? as u32,
+    }))
+}
+
+fn to_shadow_mode(object: &Value, field: &str) -> Result<Option<ShadowMode>, KickbotError> {
+    let Some(shadow_object) = object.get(field) else {
+        return Ok(None);
+    };
+
+    let mut rules = HashSet::new();
+    if shadow_object.get("rules").is_some() {
+        for (idx, rule) in deserialize(shadow_object, "rules", Value::as_array)
+            .err_parent(field)?
+            .iter()
+            .enumerate()
+        {
+            let rule = rule.as_str().ok_or(JsonError(format!(
+                "In field {field}, couldn't parse rules[{idx}] as str"
+            )))?;
+            rules.insert(rule.to_string());
+        }
+    }
+
+    Ok(Some(ShadowMode {
+        all_rules: deserialize_primitive_or(shadow_object, "all_rules", Value::as_bool, false)
+            .err_parent(field)?,
+        rules,
+        webhook: DiscordWebhook::new(
+            get_str(shadow_object, "webhook").err_parent(field)?,
+            "SpecBot",
+        )?,
+    }))
+}
+
+fn to_reason_template(object: &Value) -> Result<ReasonTemplate, KickbotError> {
+    let reason = get_str(object, "reason")?.to_string();
+    validate_template(&reason).map_err(JsonError)?;
+
+    let repeat_reason = match object.get("repeat_reason") {
+        None => None,
+        Some(_) => Some(get_str(object, "repeat_reason")?.to_string()),
+    };
+    if let Some(repeat_reason) = &repeat_reason {
+        validate_template(repeat_reason).map_err(JsonError)?;
+    }
+
+    Ok(ReasonTemplate {
+        reason,
+        repeat_reason,
+    })
+}
+
+// Optional, defaults to "No {weapon}, Read Rules" for everything
+fn to_kick_reasons(object: &Value, field: &str) -> Result<KickReasons, KickbotError> {
+    let default = KickReasons::default();
+    let Some(reasons_object) = object.get(field) else {
+        return Ok(default);
+    };
+
+    let default_template = match reasons_object.get("reason") {
+        None => default.default,
+        Some(_) => to_reason_template(reasons_object).err_parent(field)?,
+    };
+
+    let mut rules = HashMap::new();
+    if reasons_object.get("rules").is_some() {
+        let rules_object =
+            deserialize(reasons_object, "rules", Value::as_object).err_parent(field)?;
+        for (weapon, rule_object) in &rules_object {
+            rules.insert(
+                weapon.clone(),
+                to_reason_template(rule_object)
+                    .err_parent(weapon)
+                    .err_parent("rules")
+                    .err_parent(field)?,
+            );
+        }
+    }
+
+    let max_length = deserialize_primitive_or(
+        reasons_object,
+        "max_length",
+        Value::as_u64,
+        default.max_length as u64,
+    )
+    .err_parent(field)?;
+    // 0 would blank every reason
+    if max_length == 0 || max_length > MAX_REASON_LENGTH as u64 {
+        return Err(JsonError(format!(
+            "In field {field}, max_length must be between 1 and {MAX_REASON_LENGTH}"
+        )));
+    }
+
+    Ok(KickReasons {
+        default: default_template,
+        rules,
+        repeat_from: deserialize_primitive_or(
+            reasons_object,
+            "repeat_from",
+            Value::as_u64,
+            default.repeat_from,
+        )
+        .err_parent(field)?,
+        rules_url: match reasons_object.get("rules_url") {
+            None => default.rules_url,
+            Some(_) => get_str(reasons_object, "rules_url")
+                .err_parent(field)?
+                .to_string(),
+        },
+        max_length: max_length as usize,
+    })
+}
+
+fn to_rotation_control(
+    object: &Value,
+    field: &str,
+) -> Result<Option<RotationControl>, KickbotError> {
+    let Some(rotation_object) = object.get(field) else {
+        return Ok(None);
+    };
+
+    Ok(Some(RotationControl {
+        max_map_duration: deserialize_optional(rotation_object, "max_map_minutes", Value::as_f64)
+            .err_parent(field)?
+            .map(|minutes| to_duration(minutes * 60.0, "max_map_minutes"))
+            .transpose()
+            .err_parent(field)?,
+        seeding_index: deserialize_optional(rotation_object, "seeding_index", Value::as_u64)
+            .err_parent(field)?
+            .map(|index| index as usize),
+        seeding_below_players: deserialize_optional(
+            rotation_object,
+            "seeding_below_players",
+            Value::as_u64,
+        )
+        .err_parent(field)?,
+        seeding_duration: to_duration(
+            deserialize_primitive_or(rotation_object, "seeding_duration", Value::as_f64, 120.0)
+                .err_parent(field)?,
+            "seeding_duration",
+        )
+        .err_parent(field)?,
+    }))
+}
+
+fn get_str<'a>(object: &'a Value, field: &str) -> Result<&'a str, KickbotError> {
+    object
+        .get(field)
+        .ok_or(cant_find(field))?
+        .as_str()
+        .ok_or(JsonError(format!("Couldn't parse {field} as str")))
+}
+
+const DEFAULT_SERVER_NAME: &str = "![VG]";
+// Settings for detection and kicking, which only happen on the spectated server
+const SPECTATOR_ONLY_FIELDS: [&str; 5] = [
+    "kicks_to_ping",
+    "escalation",
+    "kick_verification",
+    "kick_reasons",
+    "shadow",
+];
+
+static SERVER_GUIDS_FILE_NAME: &str = "server_guids.json";
+
+// Guards the server guids file, every server saves its guid once it's matched
+static SERVER_GUIDS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// Server name -> guid, saved once a server has been matched so restarts resolve the same one
+fn load_server_guids() -> HashMap<String, String> {
+    let server_guids: HashMap<String, String> = load_json_file(SERVER_GUIDS_FILE_NAME);
+    // An empty guid matches nothing, fall back to the name instead
+    server_guids
+        .into_iter()
+        .filter(|(_, guid)| !guid.is_empty())
+        .collect()
+}
+
+pub fn save_server_guid(server_name: &str, guid: &str) -> Result<(), KickbotError> {
+    let _guard = SERVER_GUIDS_LOCK.lock().unwrap();
+    let mut server_guids = load_server_guids();
+    if guid.is_empty()
+        || server_guids
+            .get(server_name)
+            .is_some_and(|saved| saved == guid)
+    {
+        return Ok(());
+    }
+    server_guids.insert(server_name.to_string(), guid.to_string());
+    save_json_file(SERVER_GUIDS_FILE_NAME, &server_guids)
+}
+
+fn to_server_config(json: &Value, server_object: &Value) -> Result<ServerConfig, KickbotError> {
+    let mut merged = json.clone();
+    if let (Some(merged), Some(server_object)) = (merged.as_object_mut(), server_object.as_object())
+    {
+        for (key, value) in server_object {
+            merged.insert(key.clone(), value.clone());
+        }
+    }
+
+    let name = match server_object.get("name") {
+        Some(_) => get_str(server_object, "name")?,
+        None => DEFAULT_SERVER_NAME,
+    };
+
+    // A config without a servers list is the one server the client spectates
+    let spectate = deserialize_primitive_or(
+        server_object,
+        "spectate",
+        Value::as_bool,
+        server_object.is_null(),
+    )?;
+    // Only the spectated server runs detection, kick settings anywhere else would never apply
+    if !spectate {
+        if let Some(field) = SPECTATOR_ONLY_FIELDS
+            .iter()
+            .find(|field| server_object.get(**field).is_some())
+        {
+            return Err(JsonError(format!(
+                "{field} is only used on the spectated server, {name} isn't spectated"
+            )));
+        }
+    }
+
+    // A guid set in config always wins over the one saved from a previous run
+    let guid = match server_object.get("guid") {
+        Some(_) => {
+            Some(get_str(server_object, "guid")?.to_string()).filter(|guid| !guid.is_empty())
+        }
+        None => load_server_guids().remove(name),
+    };
+
+    // Pings on every kicks_to_ping-th offence, 0 would divide by zero
+    let kicks_to_ping = deserialize_primitive(&merged, "kicks_to_ping", Value::as_u64)?;
+    if kicks_to_ping == 0 {
+        return Err(JsonError(format!(
+            "kicks_to_ping must be at least 1 on {name}"
+        )));
+    }
+
+    Ok(ServerConfig {
+        name: name.to_string(),
+        guid,
+        spectate,
+        kicks_to_ping,
+        min_players_for_kick: deserialize_primitive(
+            &merged,
+            "min_players_for_kick",
+            Value::as_u64,
+        )?,
+        kick_webhook: DiscordWebhook::new(get_str(&merged, "kick_webhook")?, "SpecBot")?,
+        monitoring_webhook: DiscordWebhook::new(
+            get_str(&merged, "monitoring_webhook")?,
+            "SpecBot",
+        )?,
+        escalation: to_escalation(&merged)?,
+        immunity: to_immunity(&merged)?,
+        team_balance: to_team_balance(&merged, "team_balance")?,
+        kick_verification: to_kick_verification(&merged, "kick_verification")?,
+        kick_reasons: to_kick_reasons(&merged, "kick_reasons")?,
+        shadow: to_shadow_mode(&merged, "shadow")?,
+        rotation_control: to_rotation_control(&merged, "rotation_control")?,
+    })
+}
+
+// A config without a "servers" list monitors the one server set at the top level
+fn to_server_configs(json: &Value) -> Result<Vec<ServerConfig>, KickbotError> {
+    if json.get("servers").is_none() {
+        return Ok(vec![to_server_config(json, &Value::Null)?]);
+    }
+
+    let mut server_configs = vec![];
+    for (idx, server_object) in deserialize(json, "servers", Value::as_array)?
+        .iter()
+        .enumerate()
+    {
+        server_configs.push(
+            to_server_config(json, server_object).err_parent(format!("servers[{idx}]").as_str())?,
+        );
+    }
+    if server_configs.is_empty() {
+        return Err(JsonError("servers can't be empty".to_string()));
+    }
+    // One game client per machine, so there's exactly one server to spectate and kick on
+    let spectated = server_configs
+        .iter()
+        .filter(|server| server.spectate)
+        .count();
+    if spectated != 1 {
+        return Err(JsonError(format!(
+            "Exactly one server needs spectate set, found {spectated}"
+        )));
+    }
+    Ok(server_configs)
+}
+
 impl Config {
-    pub async fn read_config(filename: &str) -> Result<Config, KickbotError> {
+    // Only reads and checks the file, connect_webhooks is what reaches Discord
+    pub fn read_config(filename: &str) -> Result<Config, KickbotError> {
         let reader = File::open(filename).map_err(|err| {
             KickbotError::JsonError(format!(
                 "Failed to open file {}: {}",
@@ -368,11 +1281,6 @@ impl Config {
         };
 
         // can't be bothered to adapt deserialize, just hardcode
-        let kick_webhook_url = json
-            .get("kick_webhook")
-            .ok_or(JsonError("Couldn't find kick_webhook".to_string()))?
-            .as_str()
-            .ok_or(JsonError("Couldn't parse kick_webhook as str".to_string()))?;
         let monitoring_webhook_url = json
             .get("monitoring_webhook")
             .ok_or(JsonError("Couldn't find monitoring_webhook".to_string()))?
@@ -389,14 +1297,7 @@ impl Config {
 
         Ok(Config {
             bf1_path: String::from(bf1_path),
-            kicks_to_ping: deserialize_primitive(&json, "kicks_to_ping", Value::as_u64)?,
-            min_players_for_kick: deserialize_primitive(
-                &json,
-                "min_players_for_kick",
-                Value::as_u64,
-            )?,
-            kick_webhook: DiscordWebhook::new(kick_webhook_url, "SpecBot").await?,
-            monitoring_webhook: DiscordWebhook::new(monitoring_webhook_url, "SpecBot").await?,
+            monitoring_webhook: DiscordWebhook::new(monitoring_webhook_url, "SpecBot")?,
             player_similar_name_probability: deserialize_primitive(
                 &json,
                 "player_similar_name_probability",
@@ -432,10 +1333,84 @@ impl Config {
             ]),
             banned_gadgets: vec![],
             banned_weapon,
+            api_policy: to_request_policy(&json, "api_requests")?,
+            roster_policy: to_roster_policy(&json, "roster")?,
+            history_policy: to_history_policy(&json, "history")?,
+            weekly_summary: deserialize_primitive_or(
+                &json,
+                "weekly_summary",
+                Value::as_bool,
+                true,
+            )?,
+            servers: to_server_configs(&json)?,
         })
     }
 
+    // Looks up every webhook so a bad one fails at startup instead of on the first kick
+    pub async fn connect_webhooks(&self) -> Result<(), KickbotError> {
+        self.monitoring_webhook.connect().await?;
+        for server in self.servers.iter() {
+            server.kick_webhook.connect().await?;
+            server.monitoring_webhook.connect().await?;
+            if let Some(shadow) = &server.shadow {
+                shadow.webhook.connect().await?;
+            }
+        }
+        Ok(())
+    }
+
+    // The server our BF1 client spectates, only one client can run per machine
+    pub fn spectated_server(&self) -> &ServerConfig {
+        self.servers
+            .iter()
+            .find(|server| server.spectate)
+            .expect("read_config checks exactly one server is spectated")
+    }
+
+    pub fn server_by_name(&self, name: &str) -> Option<&ServerConfig> {
+        self.servers.iter().find(|server| server.name == name)
+    }
+
     pub fn are_similar(&self, string1: &str, string2: &str, probability: f64) -> bool {
         gestalt_ratio(string1, string2) >= probability
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_json_files_through_a_temp_file() {
+        let path =
+            std::env::temp_dir().join(format!("vgkickbot_{}_temp_bans.json", std::process::id()));
+        let file_name = path.to_str().unwrap();
+        let temp_bans = vec![TempBan {
+            server_id: 1,
+            persona_id: 2,
+            until: 3,
+        }];
+
+        save_json_file(file_name, &temp_bans).unwrap();
+        save_json_file(file_name, &temp_bans).unwrap();
+        let loaded: Vec<TempBan> = load_json_file(file_name);
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].persona_id, 2);
+        assert!(!Path::new(&format!("{file_name}.tmp")).exists());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_bad_escalation_steps() {
+        let ladder =
+            |steps: Value| to_escalation(&serde_json::json!({"escalation": {"steps": steps}}));
+        let step = |offences: u64, action: &str| serde_json::json!({"offences": offences, "action": action});
+
+        let ladder_ok = ladder(serde_json::json!([step(3, "ban"), step(1, "kick")])).unwrap();
+        assert_eq!(ladder_ok.step_for(2).action, EscalationAction::Kick);
+        assert_eq!(ladder_ok.step_for(3).action, EscalationAction::Ban);
+
+        assert!(ladder(serde_json::json!([step(0, "kick")])).is_err());
+        assert!(ladder(serde_json::json!([step(1, "kick"), step(1, "ban")])).is_err());
+    }
+}
@@ -32,6 +32,24 @@ pub fn log<T: Error>(err: &T) {
     out.flush().unwrap();
 }
 
+pub fn log_info(message: &str) {
+    let mut out = std::io::stdout();
+    let (_, height) = terminal::size().unwrap();
+    let _ = execute!(
+        out,
+        MoveTo(0, height - 1),
+        Clear(ClearType::CurrentLine),
+        SetAttribute(Attribute::Bold),
+        SetForegroundColor(Color::Yellow),
+        Print("Info: "),
+        SetAttribute(Attribute::NoBold),
+        Print(message),
+        SetAttribute(Attribute::Reset)
+    );
+
+    out.flush().unwrap();
+}
+
 pub fn update_status(status: StatusTypes) {
     let mut out = std::io::stdout();
     let _ = execute!(
@@ -152,20 +170,45 @@ impl Console {
         );
 
         let map_name = server_details.map.clone();
+        let mode_text = format!(
+            "{} [{}]",
+            server_details.game_mode,
+            server_details.rotation_position()
+        );
 
-        // Game Info
+        // Game Info, right aligned. Long names on a narrow terminal start at the left edge instead
         execute!(
             out,
-            MoveTo(width - player_count_text.len() as u16, status_y),
+            MoveTo(
+                width.saturating_sub(player_count_text.len() as u16),
+                status_y
+            ),
             Print(player_count_text),
-            MoveTo(width - spectator_count_text.len() as u16, status_y + 1),
+            MoveTo(
+                width.saturating_sub(spectator_count_text.len() as u16),
+                status_y + 1
+            ),
             Print(spectator_count_text),
-            MoveTo(width - server_details.name.len() as u16, status_y + 2),
+            MoveTo(
+                width.saturating_sub(server_details.name.len() as u16),
+                status_y + 2
+            ),
             Print(&server_details.name),
-            MoveTo(width - map_name.len() as u16 - 5, status_y + 3),
+            MoveTo(
+                width.saturating_sub(map_name.len() as u16 + 5),
+                status_y + 3
+            ),
             Print("Map: "),
             SetAttribute(Attribute::Bold),
             Print(map_name),
+            SetAttribute(Attribute::Reset),
+            MoveTo(
+                width.saturating_sub(mode_text.len() as u16 + 6),
+                status_y + 4
+            ),
+            Print("Mode: "),
+            SetAttribute(Attribute::Bold),
+            Print(mode_text),
             SetAttribute(Attribute::Reset)
         )
         .unwrap();
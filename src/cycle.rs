@@ -1,18 +1,20 @@
 use crate::api::bf1api::server::ServerDetails;
 use crate::api::bf1api::BF1Api;
 use crate::botstatus::{BotStatus, StatusTypes};
-use crate::config::{Config, PlayerKickHistoryRecord};
+use crate::config::{Config, ServerConfig};
 use crate::console::{log, update_status};
 use crate::errors::KickbotError;
+use crate::history::HistoryStore;
 use crate::recognition::detection::{detect, detect_player_name};
 use crate::recognition::kick_player::kick_player;
 use crate::recognition::model::{Classifier, WeaponClasses};
 use crate::recognition::ocr::OCR;
 use crate::recognition::screenshot::Screenshot;
 use crate::BotStats;
-use chrono::Local;
+use chrono::{DateTime, Local, TimeDelta, Utc};
 use enigo::Direction::{Press, Release};
 use enigo::{Enigo, Key, Keyboard, Settings};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::ops::Deref;
@@ -25,6 +27,32 @@ use tokio::time::sleep;
 pub struct RecordWeapon {
     pub(crate) name: String,
     pub(crate) category: WeaponClasses,
+    pub(crate) detected_at: DateTime<Utc>,
+}
+
+// Pending kicks older than this are dropped, they've probably left by now
+const PENDING_KICK_EXPIRY: TimeDelta = TimeDelta::minutes(30);
+
+// Saved state older than this is ignored even for the same gameId
+const ROUND_STATE_EXPIRY: TimeDelta = TimeDelta::hours(2);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PendingKick {
+    pub player_name: String,
+    pub weapon: String,
+    pub category: WeaponClasses,
+    // Unix timestamp
+    pub detected_at: i64,
+}
+
+// What's kept of a GameState across restarts
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RoundState {
+    pub game_id: String,
+    // Unix timestamp
+    pub saved_at: i64,
+    pub already_kicked: Vec<String>,
+    pub pending: Vec<PendingKick>,
 }
 
 #[derive(Clone)]
@@ -48,6 +76,54 @@ impl GameState {
             pending_kick_players: Default::default(),
         }
     }
+
+    pub fn expire_pending_kicks(&mut self) {
+        let since = Utc::now() - PENDING_KICK_EXPIRY;
+        self.pending_kick_players
+            .retain(|_, weapon| weapon.detected_at >= since);
+    }
+
+    pub fn to_round_state(&self, game_id: &str) -> RoundState {
+        RoundState {
+            game_id: game_id.to_string(),
+            saved_at: Utc::now().timestamp(),
+            already_kicked: self.already_kicked_list_players.iter().cloned().collect(),
+            pending: self
+                .pending_kick_players
+                .iter()
+                .map(|(player_name, weapon)| PendingKick {
+                    player_name: player_name.clone(),
+                    weapon: weapon.name.clone(),
+                    category: weapon.category.clone(),
+                    detected_at: weapon.detected_at.timestamp(),
+                })
+                .collect(),
+        }
+    }
+
+    // Only restores state saved for the same round, false if it was stale or for another gameId
+    pub fn restore(&mut self, round_state: RoundState, game_id: &str) -> bool {
+        let saved_at = DateTime::from_timestamp(round_state.saved_at, 0).unwrap_or_default();
+        if round_state.game_id != game_id || saved_at < Utc::now() - ROUND_STATE_EXPIRY {
+            return false;
+        }
+
+        self.already_kicked_list_players
+            .extend(round_state.already_kicked);
+        for pending in round_state.pending {
+            self.pending_kick_players.insert(
+                pending.player_name,
+                RecordWeapon {
+                    name: pending.weapon,
+                    category: pending.category,
+                    detected_at: DateTime::from_timestamp(pending.detected_at, 0)
+                        .unwrap_or_default(),
+                },
+            );
+        }
+        self.expire_pending_kicks();
+        true
+    }
 }
 
 pub struct Executors {
@@ -91,6 +167,7 @@ pub async fn do_detection(
         Option<String>,
         Option<String>,
         Option<WeaponClasses>,
+        Option<f32>,
         Option<Screenshot>,
     ),
     KickbotError,
@@ -98,7 +175,7 @@ pub async fn do_detection(
     let screenshot = Screenshot::take_screenshot()?;
     let (ocr, player_name) = detect_player_name(&screenshot, config, ocr)?;
     let Some(player_name) = player_name else {
-        return Ok((ocr, None, None, None, None));
+        return Ok((ocr, None, None, None, None, None));
     };
 
     // Max player name is 3 so probably didn't read anything
@@ -111,7 +188,7 @@ pub async fn do_detection(
             update_status(bot_status.status);
             game_state_write.no_player_count = 0;
         }
-        return Ok((ocr, None, None, None, None));
+        return Ok((ocr, None, None, None, None, None));
         // No need to continue
     } else {
         let bot_status_read = bot_status.read().await;
@@ -150,7 +227,8 @@ pub async fn do_detection(
 
     game_state_write.last_player = player_name.clone();
 
-    let (ocr, banned_weapon_name, category) = detect(&screenshot, config, ocr, classifier.deref())?;
+    let (ocr, banned_weapon_name, category, confidence) =
+        detect(&screenshot, config, ocr, classifier.deref())?;
 
     if config.save_screenshots {
         Ok((
@@ -158,17 +236,26 @@ pub async fn do_detection(
             Some(player_name),
             banned_weapon_name,
             category,
+            confidence,
             Some(screenshot),
         ))
     } else {
-        Ok((ocr, Some(player_name), banned_weapon_name, category, None))
+        Ok((
+            ocr,
+            Some(player_name),
+            banned_weapon_name,
+            category,
+            confidence,
+            None,
+        ))
     }
 }
 
 pub async fn execute(
     api: &'static BF1Api,
     config: &'static Config,
-    kick_record: Arc<Mutex<PlayerKickHistoryRecord>>,
+    server_config: &'static ServerConfig,
+    kick_record: Arc<Mutex<HistoryStore>>,
     game_state: Arc<RwLock<GameState>>,
     executors: Arc<Mutex<Executors>>,
     spec_cycle: Arc<Mutex<SpecCycle>>,
@@ -199,6 +286,7 @@ pub async fn execute(
                 maybe_player_name,
                 maybe_banned_weapon,
                 maybe_category,
+                confidence,
                 maybe_screenshot,
             )) = do_detection(
                 api,
@@ -214,35 +302,44 @@ pub async fn execute(
             {
                 if let Some(banned_weapon) = maybe_banned_weapon {
                     if let Some(player_name) = maybe_player_name {
+                        let detected_at = Utc::now();
+                        // Saved first so the kick can point at it
+                        let evidence_path = maybe_screenshot.and_then(|screenshot| {
+                            screenshot
+                                .save(
+                                    format!(
+                                        "{}-{}-{}",
+                                        player_name,
+                                        banned_weapon,
+                                        Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+                                    )
+                                    .as_str(),
+                                )
+                                .map_err(|err| log(&err))
+                                .ok()
+                        });
+
                         if let Some(category) = maybe_category {
+                            // A copy, so refreshes aren't held up while the kick is worked out
+                            let server = server.lock().await.clone();
                             kick_player(
                                 api,
                                 config,
+                                server_config,
                                 kick_record,
                                 &player_name,
                                 banned_weapon.clone(),
                                 category,
+                                confidence,
+                                evidence_path,
+                                detected_at,
                                 game_state,
-                                server.lock().await.deref(),
+                                &server,
                                 bot_stats,
                                 false,
                             )
                             .await;
                         }
-
-                        if let Some(screenshot) = maybe_screenshot {
-                            let _ = screenshot
-                                .save(
-                                    format!(
-                                        "{}-{}-{}",
-                                        player_name,
-                                        banned_weapon,
-                                        Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
-                                    )
-                                    .as_str(),
-                                )
-                                .map_err(|err| log(&err));
-                        }
                     }
                 }
 
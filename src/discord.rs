@@ -1,53 +1,81 @@
+use crate::api::bf1api::models::PlayerStats;
+use crate::api::bf1api::server::ServerDetails;
 use crate::api::errors::BF1ApiError;
-use crate::config::{dates_to_csv_string, weapon_kick_records_to_csv_strings};
+use crate::config::dates_to_csv_string;
 use crate::errors::KickbotError;
 use crate::errors::KickbotError::DiscordError;
+use crate::statistics::{top, EnforcementReport};
 use crate::BotStats;
 use chrono::{DateTime, TimeDelta, Utc};
+use reqwest::Url;
 use serenity::all::{Color, CreateEmbed, CreateEmbedAuthor, ExecuteWebhook, Http, Webhook};
+use serenity::utils::parse_webhook;
 use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::OnceCell;
 
 #[derive(Debug)]
 pub struct DiscordWebhook {
     pub http: Http,
-    webhook: Webhook,
+    url: String,
+    // Fetched from Discord on first use, so reading the config doesn't need the network
+    webhook: OnceCell<Webhook>,
     author: CreateEmbedAuthor,
 }
 
 impl DiscordWebhook {
-    pub async fn new(url: &str, username: &str) -> Result<DiscordWebhook, KickbotError> {
-        let http = Http::new("token");
-
-        let webhook = Webhook::from_url(&http, url).await.map_err(|err| {
-            DiscordError(format!(
-                "Error connecting to monitoring webhook: {}",
-                err.to_string()
-            ))
-        })?;
-
-        let author = CreateEmbedAuthor::new(username);
+    // Only checks the url is a webhook url, connect is what reaches Discord
+    pub fn new(url: &str, username: &str) -> Result<DiscordWebhook, KickbotError> {
+        let is_webhook = Url::parse(url)
+            .ok()
+            .is_some_and(|parsed| parse_webhook(&parsed).is_some());
+        if !is_webhook {
+            return Err(DiscordError(format!("{url} isn't a Discord webhook url")));
+        }
 
         Ok(DiscordWebhook {
-            http,
-            webhook,
-            author,
+            http: Http::new("token"),
+            url: url.to_string(),
+            webhook: OnceCell::new(),
+            author: CreateEmbedAuthor::new(username),
         })
     }
+
+    pub async fn connect(&self) -> Result<&Webhook, KickbotError> {
+        self.webhook
+            .get_or_try_init(|| async {
+                Webhook::from_url(&self.http, &self.url)
+                    .await
+                    .map_err(|err| {
+                        DiscordError(format!("Error connecting to webhook: {}", err.to_string()))
+                    })
+            })
+            .await
+    }
 }
 
 pub async fn announce_monitoring(
     monitoring_webhook: &DiscordWebhook,
     start_time: DateTime<Utc>,
+    server: &ServerDetails,
 ) -> Result<(), KickbotError> {
     let start_time_string = start_time.format("%H:%M:%S").to_string();
     let embed = CreateEmbed::new()
         .title("Now Monitoring")
-        .description(format!("Began monitoring at {}", start_time_string))
+        .description(format!(
+            "Began monitoring at {}\n\n{}\nMap: {}\nMode: {} (rotation {})",
+            start_time_string,
+            server.name,
+            server.map,
+            server.game_mode,
+            server.rotation_position()
+        ))
         .color(Color::DARK_GREEN);
 
     let builder = ExecuteWebhook::new().embed(embed).username("Spec Bot");
     monitoring_webhook
-        .webhook
+        .connect()
+        .await?
         .execute(&monitoring_webhook.http, false, builder)
         .await
         .map_err(|err| {
@@ -110,7 +138,8 @@ pub async fn announce_shutdown(
 
     let builder = ExecuteWebhook::new().embed(embed).username("Spec Bot");
     monitoring_webhook
-        .webhook
+        .connect()
+        .await?
         .execute(&monitoring_webhook.http, false, builder)
         .await
         .map_err(|err| {
@@ -123,13 +152,68 @@ pub async fn announce_shutdown(
     Ok(())
 }
 
+pub async fn announce_weekly_summary(
+    monitoring_webhook: &DiscordWebhook,
+    report: &EnforcementReport,
+) -> Result<(), KickbotError> {
+    let top_line = |counts| {
+        top(counts, 3)
+            .iter()
+            .map(|(name, count)| format!("{name} ({count})"))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    let busiest_hour = report
+        .busiest_hour()
+        .map(|hour| format!("{hour:02}:00"))
+        .unwrap_or("-".to_string());
+    let latency = match &report.latency {
+        Some(latency) => format!("{}s median, {}s p95", latency.median_secs, latency.p95_secs),
+        None => "-".to_string(),
+    };
+
+    let embed = CreateEmbed::new()
+        .title("Weekly Summary")
+        .description(format!(
+            "Kicks: {}\n Pardoned: {} ({:.1}% false positives)\n Not confirmed: {}\n Warnings: {}\n\n Top rules: {}\n Top maps: {}\n Servers: {}\n\n Busiest hour: {}\n Busiest day: {}\n Detection to kick: {}",
+            report.kicks,
+            report.pardoned,
+            report.false_positive_rate * 100.0,
+            report.unconfirmed,
+            report.warnings,
+            top_line(&report.by_rule),
+            top_line(&report.by_map),
+            top_line(&report.by_server),
+            busiest_hour,
+            report.busiest_weekday().unwrap_or("-"),
+            latency
+        ))
+        .color(Color::BLUE);
+
+    let builder = ExecuteWebhook::new().embed(embed).username("Spec Bot");
+    monitoring_webhook
+        .connect()
+        .await?
+        .execute(&monitoring_webhook.http, false, builder)
+        .await
+        .map_err(|err| {
+            DiscordError(format!(
+                "Error sending weekly summary message: {}",
+                err.to_string()
+            ))
+        })?;
+
+    Ok(())
+}
+
 async fn announce_kick(
     kick_webhook: &DiscordWebhook,
     embed: CreateEmbed,
 ) -> Result<(), KickbotError> {
     let builder = ExecuteWebhook::new().embed(embed).username("Spec Bot");
     kick_webhook
-        .webhook
+        .connect()
+        .await?
         .execute(&kick_webhook.http, false, builder)
         .await
         .map_err(|err| DiscordError(format!("Error sending kick message: {}", err.to_string())))?;
@@ -137,17 +221,28 @@ async fn announce_kick(
     Ok(())
 }
 
+fn stats_line(stats: Option<&PlayerStats>) -> String {
+    match stats {
+        Some(stats) => format!("\n Stats: {}", stats.summary()),
+        None => String::new(),
+    }
+}
+
 pub async fn announce_kick_success(
     kick_webhook: &DiscordWebhook,
     player_name: &str,
     player_pid: &str,
     reason: &str,
+    stats: Option<&PlayerStats>,
 ) -> Result<(), KickbotError> {
     let embed = CreateEmbed::new()
         .title("Kick Success")
         .description(format!(
-            "Name: {}\nReason: {}\n PID: {}",
-            player_name, reason, player_pid
+            "Name: {}\nReason: {}\n PID: {}{}",
+            player_name,
+            reason,
+            player_pid,
+            stats_line(stats)
         ))
         .color(Color::DARK_GREEN);
     announce_kick(kick_webhook, embed).await
@@ -170,22 +265,130 @@ pub async fn announce_kick_fail(
     announce_kick(kick_webhook, embed).await
 }
 
+pub async fn announce_shadow_kick(
+    shadow_webhook: &DiscordWebhook,
+    server_name: &str,
+    player_name: &str,
+    player_pid: &str,
+    reason: &str,
+) -> Result<(), KickbotError> {
+    let embed = CreateEmbed::new()
+        .title("Shadow Kick")
+        .description(format!(
+            "Server: {}\nName: {}\nReason: {}\n PID: {}\n Not kicked, shadow mode is on for this rule",
+            server_name, player_name, reason, player_pid
+        ))
+        .color(Color::LIGHT_GREY);
+    announce_kick(shadow_webhook, embed).await
+}
+
+pub async fn announce_kick_unconfirmed(
+    kick_webhook: &DiscordWebhook,
+    player_name: &str,
+    player_pid: &str,
+    reason: &str,
+    attempts: u32,
+) -> Result<(), KickbotError> {
+    let embed = CreateEmbed::new()
+        .title("Kick Unconfirmed")
+        .description(format!(
+            "Name: {}\nReason: {}\n PID: {}\n Still on the server after {} kick attempts",
+            player_name, reason, player_pid, attempts
+        ))
+        .color(Color::ORANGE);
+    announce_kick(kick_webhook, embed).await
+}
+
+pub async fn announce_ban_success(
+    kick_webhook: &DiscordWebhook,
+    player_name: &str,
+    player_pid: &str,
+    reason: &str,
+    offences: u64,
+    duration: Option<TimeDelta>,
+) -> Result<(), KickbotError> {
+    let length = match duration {
+        Some(duration) => format!("Banned for {} hours", duration.num_hours()),
+        None => "Banned".to_string(),
+    };
+    let embed = CreateEmbed::new()
+        .title("Ban Success")
+        .description(format!(
+            "Name: {}\nReason: {}\n PID: {}\n {} on offence {}",
+            player_name, reason, player_pid, length, offences
+        ))
+        .color(Color::DARK_GREEN);
+    announce_kick(kick_webhook, embed).await
+}
+
+pub async fn announce_player_warning(
+    kick_webhook: &DiscordWebhook,
+    player_name: &str,
+    player_pid: &str,
+    reason: &str,
+    offences: u64,
+) -> Result<(), KickbotError> {
+    let embed = CreateEmbed::new()
+        .title("Warning")
+        .description(format!(
+            "Name: {}\nReason: {}\n PID: {}\n Not kicked, offence {}",
+            player_name, reason, player_pid, offences
+        ))
+        .color(Color::GOLD);
+    announce_kick(kick_webhook, embed).await
+}
+
+pub async fn announce_ban_fail(
+    kick_webhook: &DiscordWebhook,
+    player_name: &str,
+    player_pid: &str,
+    reason: &str,
+    error: &str,
+) -> Result<(), KickbotError> {
+    let embed = CreateEmbed::new()
+        .title("Ban Failed")
+        .description(format!(
+            "Name: {}\nReason: {}\n PID: {}\n Error: {}\n Kicking instead",
+            player_name, reason, player_pid, error
+        ))
+        .color(Color::DARK_RED);
+    announce_kick(kick_webhook, embed).await
+}
+
 pub async fn announce_player_multiple_kicks(
     kick_webhook: &DiscordWebhook,
     player_name: &str,
     player_pid: &str,
-    number_of_kicks: u64,
+    active_kicks: u64,
+    lifetime_kicks: u64,
     record: &HashMap<String, Vec<DateTime<Utc>>>,
+    aliases: &[String],
+    stats: Option<&PlayerStats>,
 ) -> Result<(), KickbotError> {
     let id = "<admin id>";
     let embed_msg_content = format!("<@&{}>\n", id);
 
+    let other_names: Vec<&str> = aliases
+        .iter()
+        .map(String::as_str)
+        .filter(|alias| *alias != player_name)
+        .collect();
+    let aliases_line = match other_names.is_empty() {
+        true => String::new(),
+        false => format!("\n Also kicked as: `{}`", other_names.join("`, `")),
+    };
+
     let embed = CreateEmbed::new()
         .color(Color::DARK_RED)
         .title("Multiple Kicks")
         .description(format!(
-            "Player`{}`\nPID:`{}`\n has {} lifetime kicks",
-            player_name, player_pid, number_of_kicks
+            "Player`{}`\nPID:`{}`\n has {} active and {} lifetime kicks{}{}",
+            player_name,
+            player_pid,
+            active_kicks,
+            lifetime_kicks,
+            aliases_line,
+            stats_line(stats)
         ));
 
     let builder = ExecuteWebhook::new()
@@ -193,7 +396,8 @@ pub async fn announce_player_multiple_kicks(
         .content(embed_msg_content)
         .username("Spec Bot");
     kick_webhook
-        .webhook
+        .connect()
+        .await?
         .execute(&kick_webhook.http, false, builder)
         .await
         .map_err(|err| {
@@ -216,7 +420,8 @@ pub async fn announce_player_multiple_kicks(
 
     let builder = ExecuteWebhook::new().content(content).username("Spec Bot");
     kick_webhook
-        .webhook
+        .connect()
+        .await?
         .execute(&kick_webhook.http, false, builder)
         .await
         .map_err(|err| {
@@ -229,13 +434,91 @@ pub async fn announce_player_multiple_kicks(
     Ok(())
 }
 
+pub async fn announce_team_imbalance(
+    monitoring_webhook: &DiscordWebhook,
+    server: &ServerDetails,
+    imbalanced_for: Duration,
+    moved_players: &Vec<String>,
+) -> Result<(), KickbotError> {
+    let mut description = format!(
+        "{}: {} players\n{}: {} players\nImbalanced for {}",
+        server.team1_name,
+        server.team1.len(),
+        server.team2_name,
+        server.team2.len(),
+        get_time_difference_string(TimeDelta::from_std(imbalanced_for).unwrap_or_default())
+    );
+    if !moved_players.is_empty() {
+        description = format!("{description}\n\nMoved: {}", moved_players.join(", "));
+    }
+
+    let embed = CreateEmbed::new()
+        .color(Color::ORANGE)
+        .title("Teams Imbalanced")
+        .description(description);
+    let builder = ExecuteWebhook::new().username("Spec Bot").embed(embed);
+    monitoring_webhook
+        .connect()
+        .await?
+        .execute(&monitoring_webhook.http, false, builder)
+        .await
+        .map_err(|err| {
+            DiscordError(format!(
+                "Error sending team imbalance message: {}",
+                err.to_string()
+            ))
+        })?;
+
+    Ok(())
+}
+
+pub async fn announce_map_change(
+    monitoring_webhook: &DiscordWebhook,
+    server: &ServerDetails,
+    level_index: usize,
+    reason: &str,
+) -> Result<(), KickbotError> {
+    let next_map = server
+        .rotation
+        .get(level_index)
+        .map(|map| format!("{} ({})", map.map_pretty_name, map.mode_pretty_name))
+        .unwrap_or_default();
+
+    let embed = CreateEmbed::new()
+        .color(Color::BLUE)
+        .title("Map Changed")
+        .description(format!(
+            "{} -> {}\nRotation {}/{}\nReason: {}",
+            server.map,
+            next_map,
+            level_index + 1,
+            server.rotation.len(),
+            reason
+        ));
+    let builder = ExecuteWebhook::new().username("Spec Bot").embed(embed);
+    monitoring_webhook
+        .connect()
+        .await?
+        .execute(&monitoring_webhook.http, false, builder)
+        .await
+        .map_err(|err| {
+            DiscordError(format!(
+                "Error sending map change message: {}",
+                err.to_string()
+            ))
+        })?;
+
+    Ok(())
+}
+
 pub async fn announce_bot_crashed(monitoring_webhook: &DiscordWebhook) -> Result<(), KickbotError> {
     let embed = CreateEmbed::new()
         .color(Color::DARK_RED)
         .description("BF1 Crashed, restarting...");
     let builder = ExecuteWebhook::new().username("Spec Bot").embed(embed);
     monitoring_webhook
-        .webhook
+        .connect()
+        .await?
         .execute(&monitoring_webhook.http, false, builder)
         .await
         .map_err(|err| {
@@ -14,6 +14,7 @@ pub enum KickbotError {
     ModelError(String),
     TesseractError(String),
     JsonError(String),
+    DatabaseError(String),
 }
 
 impl Error for KickbotError {}
@@ -40,6 +41,9 @@ impl Display for KickbotError {
             KickbotError::JsonError(ref err) => {
                 write!(f, "[JSON Error] {}", err)
             }
+            KickbotError::DatabaseError(ref err) => {
+                write!(f, "[Database Error] {}", err)
+            }
         }
     }
 }
@@ -86,6 +90,12 @@ impl From<csv::Error> for KickbotError {
     }
 }
 
+impl From<rusqlite::Error> for KickbotError {
+    fn from(err: rusqlite::Error) -> Self {
+        KickbotError::DatabaseError(err.to_string())
+    }
+}
+
 impl From<KickbotError> for io::Error {
     fn from(err: KickbotError) -> Self {
         io::Error::new(ErrorKind::Other, err)
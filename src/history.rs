@@ -0,0 +1,1134 @@
+use crate::api::bf1api::BF1Api;
+use crate::console::{log, log_info};
+use crate::errors::KickbotError;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+static DATABASE_FILE_NAME: &str = "kick_history.db";
+
+// Every kick is appended here and synced before it goes into the database, so a hard kill
+// can't lose it. Emptied once the database has been checkpointed
+static JOURNAL_FILE_NAME: &str = "kick_journal.jsonl";
+
+pub const JOURNAL_COMPACT_INTERVAL: Duration = Duration::from_secs(600);
+
+// The old history format, imported once then renamed so it isn't imported again
+static LEGACY_CSV_FILE_NAME: &str = "kick_history.csv";
+static IMPORTED_CSV_FILE_NAME: &str = "kick_history.csv.imported";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS kicks (
+    id INTEGER PRIMARY KEY,
+    persona_id INTEGER,
+    player_name TEXT NOT NULL,
+    rule TEXT NOT NULL,
+    reason TEXT NOT NULL DEFAULT '',
+    kicked_at INTEGER NOT NULL,
+    server TEXT NOT NULL DEFAULT '',
+    game_id TEXT NOT NULL DEFAULT '',
+    map TEXT NOT NULL DEFAULT '',
+    confidence REAL,
+    evidence_path TEXT
+);
+CREATE INDEX IF NOT EXISTS kicks_player_name ON kicks (player_name);
+CREATE INDEX IF NOT EXISTS kicks_persona_id ON kicks (persona_id);
+CREATE INDEX IF NOT EXISTS kicks_kicked_at ON kicks (kicked_at);
+CREATE TABLE IF NOT EXISTS aliases (
+    persona_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    PRIMARY KEY (persona_id, name)
+);
+CREATE INDEX IF NOT EXISTS aliases_name ON aliases (name);
+";
+
+// Applied in order on top of SCHEMA, the database's user_version is how many have been applied
+const MIGRATIONS: [&str; 7] = [
+    "ALTER TABLE kicks ADD COLUMN pardoned_at INTEGER",
+    "CREATE TABLE kicks_archive (
+        id INTEGER PRIMARY KEY,
+        persona_id INTEGER,
+        player_name TEXT NOT NULL,
+        rule TEXT NOT NULL,
+        reason TEXT NOT NULL DEFAULT '',
+        kicked_at INTEGER NOT NULL,
+        server TEXT NOT NULL DEFAULT '',
+        game_id TEXT NOT NULL DEFAULT '',
+        map TEXT NOT NULL DEFAULT '',
+        confidence REAL,
+        evidence_path TEXT,
+        pardoned_at INTEGER
+    );
+    CREATE INDEX kicks_archive_persona_id ON kicks_archive (persona_id);",
+    "ALTER TABLE kicks ADD COLUMN detected_at INTEGER;
+    ALTER TABLE kicks_archive ADD COLUMN detected_at INTEGER;
+    CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);",
+    "ALTER TABLE kicks ADD COLUMN confirmed INTEGER;
+    ALTER TABLE kicks_archive ADD COLUMN confirmed INTEGER;",
+    "ALTER TABLE kicks ADD COLUMN action TEXT NOT NULL DEFAULT 'kick';
+    ALTER TABLE kicks_archive ADD COLUMN action TEXT NOT NULL DEFAULT 'kick';",
+    "CREATE TABLE name_resolve_attempts (
+        name TEXT PRIMARY KEY,
+        attempts INTEGER NOT NULL,
+        last_attempt INTEGER NOT NULL
+    );",
+    "CREATE TABLE removed_kicks (
+        persona_id INTEGER,
+        player_name TEXT NOT NULL,
+        rule TEXT NOT NULL,
+        kicked_at INTEGER NOT NULL,
+        game_id TEXT NOT NULL DEFAULT ''
+    );",
+];
+
+// Names that still don't resolve after this many starts are most likely deleted or renamed
+// accounts, they're kept in the history but no longer looked up
+const MAX_RESOLVE_ATTEMPTS: i64 = 5;
+
+// Column order shared by kicks and kicks_archive
+const KICK_COLUMNS: &str = "id, persona_id, player_name, rule, reason, kicked_at, server, game_id, map, confidence, evidence_path, pardoned_at, detected_at, confirmed, action";
+
+// Both tables as one, for anything that looks at the whole history rather than active offences
+fn all_kicks_table() -> String {
+    format!("(SELECT {KICK_COLUMNS} FROM kicks UNION ALL SELECT {KICK_COLUMNS} FROM kicks_archive)")
+}
+
+// What was done about an offence. Warnings are kept so escalation can count them, but they
+// aren't kicks and stay out of kick counts and statistics
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KickAction {
+    Warn,
+    #[default]
+    Kick,
+    TempBan,
+    Ban,
+}
+
+impl KickAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KickAction::Warn => "warn",
+            KickAction::Kick => "kick",
+            KickAction::TempBan => "temp_ban",
+            KickAction::Ban => "ban",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "warn" => Some(KickAction::Warn),
+            "kick" => Some(KickAction::Kick),
+            "temp_ban" => Some(KickAction::TempBan),
+            "ban" => Some(KickAction::Ban),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionAction {
+    // Moved out of the kicks table, still counted in lifetime kicks
+    Archive,
+    Delete,
+}
+
+impl RetentionAction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "archive" => Some(RetentionAction::Archive),
+            "delete" => Some(RetentionAction::Delete),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryPolicy {
+    // Kicks older than this don't count as active offences, None keeps them active forever
+    pub offence_decay: Option<TimeDelta>,
+    // Kicks older than this are archived or deleted, None keeps everything
+    pub retention: Option<TimeDelta>,
+    pub retention_action: RetentionAction,
+}
+
+impl Default for HistoryPolicy {
+    fn default() -> Self {
+        HistoryPolicy {
+            offence_decay: None,
+            retention: None,
+            retention_action: RetentionAction::Archive,
+        }
+    }
+}
+
+impl HistoryPolicy {
+    pub fn active_since(&self) -> Option<DateTime<Utc>> {
+        self.offence_decay
+            .and_then(|decay| Utc::now().checked_sub_signed(decay))
+    }
+}
+
+// Where the kick happened and what it was based on, filled in before the kick is sent
+#[derive(Debug, Clone, Default)]
+pub struct KickContext {
+    pub map: String,
+    pub confidence: Option<f32>,
+    pub evidence_path: Option<String>,
+    // When the banned weapon was first seen, well before the kick for pending kicks
+    pub detected_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KickEntry {
+    // 0 until it's in the database
+    #[serde(default)]
+    pub id: i64,
+    // Missing for kicks imported from the CSV history until they're migrated
+    pub persona_id: Option<u64>,
+    // Name at the time of the kick, with the platoon tag
+    pub player_name: String,
+    pub rule: String,
+    pub reason: String,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub kicked_at: DateTime<Utc>,
+    pub server: String,
+    pub game_id: String,
+    pub map: String,
+    pub confidence: Option<f32>,
+    pub evidence_path: Option<String>,
+    // Pardoned kicks are kept but don't count as offences
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    pub pardoned_at: Option<DateTime<Utc>>,
+    // Missing for kicks recorded before detection times were kept
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    pub detected_at: Option<DateTime<Utc>>,
+    // Whether they were seen leaving, None when kick verification is off
+    #[serde(default)]
+    pub confirmed: Option<bool>,
+    // Missing for kicks recorded before warnings were told apart
+    #[serde(default)]
+    pub action: KickAction,
+}
+
+impl KickEntry {
+    pub fn new(
+        persona_id: u64,
+        player_name: &str,
+        rule: &str,
+        reason: &str,
+        server: &str,
+        game_id: &str,
+        context: &KickContext,
+    ) -> Self {
+        KickEntry {
+            id: 0,
+            persona_id: Some(persona_id),
+            player_name: player_name.to_string(),
+            rule: rule.to_string(),
+            reason: reason.to_string(),
+            kicked_at: Utc::now(),
+            server: server.to_string(),
+            game_id: game_id.to_string(),
+            map: context.map.clone(),
+            confidence: context.confidence,
+            evidence_path: context.evidence_path.clone(),
+            pardoned_at: None,
+            detected_at: context.detected_at,
+            confirmed: None,
+            action: KickAction::Kick,
+        }
+    }
+
+    // Detection to kick, None if the detection time wasn't recorded
+    pub fn latency(&self) -> Option<TimeDelta> {
+        self.detected_at
+            .map(|detected_at| self.kicked_at - detected_at)
+    }
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(KickEntry {
+            id: row.get("id")?,
+            persona_id: row.get::<_, Option<i64>>("persona_id")?.map(|id| id as u64),
+            player_name: row.get("player_name")?,
+            rule: row.get("rule")?,
+            reason: row.get("reason")?,
+            kicked_at: DateTime::from_timestamp(row.get("kicked_at")?, 0).unwrap_or_default(),
+            server: row.get("server")?,
+            game_id: row.get("game_id")?,
+            map: row.get("map")?,
+            confidence: row.get::<_, Option<f64>>("confidence")?.map(|c| c as f32),
+            evidence_path: row.get("evidence_path")?,
+            pardoned_at: row
+                .get::<_, Option<i64>>("pardoned_at")?
+                .and_then(|pardoned_at| DateTime::from_timestamp(pardoned_at, 0)),
+            detected_at: row
+                .get::<_, Option<i64>>("detected_at")?
+                .and_then(|detected_at| DateTime::from_timestamp(detected_at, 0)),
+            confirmed: row.get("confirmed")?,
+            action: KickAction::from_name(row.get::<_, String>("action")?.as_str())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryStore {
+    connection: Connection,
+    journal: File,
+    journal_path: PathBuf,
+}
+
+impl HistoryStore {
+    pub fn open() -> Result<Self, KickbotError> {
+        let store = HistoryStore::open_path(DATABASE_FILE_NAME, JOURNAL_FILE_NAME)?;
+        store.replay_journal()?;
+        store.import_legacy_csv()?;
+        Ok(store)
+    }
+
+    // For the CLI, which can run alongside the bot. Leaves the journal and the legacy CSV to
+    // the bot, replaying or compacting from here could truncate kicks the bot is still writing
+    pub fn open_shared() -> Result<Self, KickbotError> {
+        HistoryStore::open_path(DATABASE_FILE_NAME, JOURNAL_FILE_NAME)
+    }
+
+    pub fn open_path<P: AsRef<Path>, J: AsRef<Path>>(
+        path: P,
+        journal_path: J,
+    ) -> Result<Self, KickbotError> {
+        let connection = Connection::open(path)?;
+        // The journal is what makes a kick durable, the database only needs to be consistent
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(SCHEMA)?;
+        migrate(&connection)?;
+        let journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)?;
+        Ok(HistoryStore {
+            connection,
+            journal,
+            journal_path: journal_path.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn record_kick(&self, entry: &KickEntry) -> Result<i64, KickbotError> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        (&self.journal).write_all(line.as_bytes())?;
+        self.journal.sync_data()?;
+
+        self.insert_kick(entry)
+    }
+
+    fn insert_kick(&self, entry: &KickEntry) -> Result<i64, KickbotError> {
+        self.connection.execute(
+            // Ids carry on past the archive so an id always means the same kick
+            "INSERT INTO kicks (id, persona_id, player_name, rule, reason, kicked_at, server, game_id, map, confidence, evidence_path, detected_at, confirmed, action)
+             VALUES (
+                (SELECT MAX(COALESCE((SELECT MAX(id) FROM kicks), 0), COALESCE((SELECT MAX(id) FROM kicks_archive), 0)) + 1),
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13
+             )",
+            params![
+                entry.persona_id.map(|id| id as i64),
+                entry.player_name,
+                entry.rule,
+                entry.reason,
+                entry.kicked_at.timestamp(),
+                entry.server,
+                entry.game_id,
+                entry.map,
+                entry.confidence.map(|c| c as f64),
+                entry.evidence_path,
+                entry.detected_at.map(|detected_at| detected_at.timestamp()),
+                entry.confirmed,
+                entry.action.as_str(),
+            ],
+        )?;
+        let id = self.connection.last_insert_rowid();
+        if let Some(persona_id) = entry.persona_id {
+            self.remember_alias(persona_id, &entry.player_name, entry.kicked_at)?;
+        }
+        Ok(id)
+    }
+
+    // The same kick can't be recorded twice in a second, so that's enough to spot what's already
+    // in. Removed kicks count as in, they can still be in the journal until it's compacted
+    fn contains_kick(&self, entry: &KickEntry) -> Result<bool, KickbotError> {
+        let exists: bool = self.connection.query_row(
+            format!(
+                "SELECT EXISTS (SELECT 1 FROM (
+                    SELECT persona_id, player_name, rule, kicked_at, game_id FROM {}
+                    UNION ALL SELECT persona_id, player_name, rule, kicked_at, game_id FROM removed_kicks
+                ) WHERE persona_id IS ?1 AND player_name = ?2 AND rule = ?3 AND kicked_at = ?4 AND game_id = ?5)",
+                all_kicks_table()
+            )
+            .as_str(),
+            params![
+                entry.persona_id.map(|id| id as i64),
+                entry.player_name,
+                entry.rule,
+                entry.kicked_at.timestamp(),
+                entry.game_id,
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(exists)
+    }
+
+    // Puts back any kicks from the journal that didn't make it into the database, then compacts
+    fn replay_journal(&self) -> Result<(), KickbotError> {
+        let reader = BufReader::new(File::open(&self.journal_path)?);
+
+        let mut replayed = 0;
+        let transaction = self.connection.unchecked_transaction()?;
+        for (line, result) in reader.lines().enumerate() {
+            let text = result?;
+            if text.trim().is_empty() {
+                continue;
+            }
+            // The last line can be cut short if the bot died mid write
+            let entry: KickEntry = match serde_json::from_str(&text) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log(&KickbotError::JsonError(format!(
+                        "Skipping kick journal line {}, {}",
+                        line + 1,
+                        err
+                    )));
+                    continue;
+                }
+            };
+            if !self.contains_kick(&entry)? {
+                self.insert_kick(&entry)?;
+                replayed += 1;
+            }
+        }
+        transaction.commit()?;
+
+        if replayed > 0 {
+            log_info(format!("Replayed {replayed} kicks from {JOURNAL_FILE_NAME}").as_str());
+        }
+        self.compact()
+    }
+
+    // false if a reader kept the checkpoint from finishing, so not everything is on disk yet
+    fn checkpoint(&self) -> Result<bool, KickbotError> {
+        let busy: i64 =
+            self.connection
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+        Ok(busy == 0)
+    }
+
+    // Flushes the database to disk so the journal can be emptied
+    pub fn compact(&self) -> Result<(), KickbotError> {
+        if self.journal.metadata()?.len() == 0 {
+            return Ok(());
+        }
+        // The journal is all there is for kicks that didn't make it, keep it for next time
+        if !self.checkpoint()? {
+            log_info("Kick history checkpoint didn't finish, keeping the journal until next time");
+            return Ok(());
+        }
+        // Truncated through a new handle, the append only one can't be resized on Windows
+        File::create(&self.journal_path)?.sync_all()?;
+        Ok(())
+    }
+
+    pub fn remember_alias(
+        &self,
+        persona_id: u64,
+        name: &str,
+        seen_at: DateTime<Utc>,
+    ) -> Result<(), KickbotError> {
+        self.connection.execute(
+            "INSERT INTO aliases (persona_id, name, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT (persona_id, name) DO UPDATE SET
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen)",
+            params![persona_id as i64, name, seen_at.timestamp()],
+        )?;
+        Ok(())
+    }
+
+    // The persona most recently seen with this name, with or without the platoon tag
+    pub fn persona_for_name(&self, name: &str) -> Result<Option<u64>, KickbotError> {
+        let persona_id: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT persona_id FROM aliases WHERE name = ?1 OR (name LIKE '[%' AND substr(name, instr(name, ']') + 1) = ?1)
+                 ORDER BY last_seen DESC LIMIT 1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(persona_id.map(|id| id as u64))
+    }
+
+    // Most recently seen first
+    pub fn names_for_persona(&self, persona_id: u64) -> Result<Vec<String>, KickbotError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name FROM aliases WHERE persona_id = ?1 ORDER BY last_seen DESC")?;
+        let names = statement
+            .query_map(params![persona_id as i64], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(names)
+    }
+
+    // Warnings included, what escalation goes by. since None counts every offence
+    pub fn count_offences(
+        &self,
+        persona_id: u64,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<u64, KickbotError> {
+        let count: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM kicks WHERE persona_id = ?1 AND kicked_at >= ?2
+                AND pardoned_at IS NULL",
+            params![
+                persona_id as i64,
+                since.map_or(i64::MIN, |since| since.timestamp())
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(count as u64)
+    }
+
+    // since None counts every kick. Unconfirmed kicks count too, they were still kicked for it
+    pub fn count_kicks(
+        &self,
+        persona_id: u64,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<u64, KickbotError> {
+        let count: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM kicks WHERE persona_id = ?1 AND kicked_at >= ?2
+                AND pardoned_at IS NULL AND action != 'warn'",
+            params![
+                persona_id as i64,
+                since.map_or(i64::MIN, |since| since.timestamp())
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(count as u64)
+    }
+
+    // Includes pardoned and archived kicks
+    pub fn player_kicks(&self, persona_id: u64) -> Result<Vec<KickEntry>, KickbotError> {
+        self.query_kicks(
+            format!(
+                "SELECT * FROM {} WHERE persona_id = ?1 ORDER BY kicked_at",
+                all_kicks_table()
+            )
+            .as_str(),
+            params![persona_id as i64],
+        )
+    }
+
+    // Rule -> kick times, the layout the multiple kicks announcement uses
+    pub fn kicks_by_rule(
+        &self,
+        persona_id: u64,
+    ) -> Result<HashMap<String, Vec<DateTime<Utc>>>, KickbotError> {
+        let mut kicks_by_rule: HashMap<String, Vec<DateTime<Utc>>> = HashMap::new();
+        let entries = self.query_kicks(
+            "SELECT * FROM kicks WHERE persona_id = ?1 ORDER BY kicked_at",
+            params![persona_id as i64],
+        )?;
+        for entry in entries {
+            if entry.pardoned_at.is_some() || entry.action == KickAction::Warn {
+                continue;
+            }
+            kicks_by_rule
+                .entry(entry.rule)
+                .or_default()
+                .push(entry.kicked_at);
+        }
+        Ok(kicks_by_rule)
+    }
+
+    // Every kick that isn't pardoned, archived ones included
+    pub fn count_lifetime_kicks(&self, persona_id: u64) -> Result<u64, KickbotError> {
+        let count: i64 = self.connection.query_row(
+            "SELECT (SELECT COUNT(*) FROM kicks WHERE persona_id = ?1 AND pardoned_at IS NULL
+                    AND action != 'warn')
+                + (SELECT COUNT(*) FROM kicks_archive WHERE persona_id = ?1 AND pardoned_at IS NULL
+                    AND action != 'warn')",
+            params![persona_id as i64],
+            |row| row.get(0),
+        )?;
+        Ok(count as u64)
+    }
+
+    // Returns how many kicks were archived or deleted
+    pub fn apply_retention(&self, policy: &HistoryPolicy) -> Result<u64, KickbotError> {
+        let Some(retention) = policy.retention else {
+            return Ok(0);
+        };
+        // Anything else would archive or delete the whole history
+        let before = Utc::now()
+            .checked_sub_signed(retention)
+            .filter(|_| retention > TimeDelta::zero())
+            .ok_or(KickbotError::JsonError(format!(
+                "Invalid retention of {} days",
+                retention.num_days()
+            )))?
+            .timestamp();
+
+        let transaction = self.connection.unchecked_transaction()?;
+        if policy.retention_action == RetentionAction::Archive {
+            self.connection.execute(
+                format!(
+                    "INSERT OR REPLACE INTO kicks_archive ({KICK_COLUMNS})
+                     SELECT {KICK_COLUMNS} FROM kicks WHERE kicked_at < ?1"
+                )
+                .as_str(),
+                params![before],
+            )?;
+        }
+        let removed = self
+            .connection
+            .execute("DELETE FROM kicks WHERE kicked_at < ?1", params![before])?;
+        transaction.commit()?;
+        Ok(removed as u64)
+    }
+
+    pub fn get_meta(&self, key: &str) -> Result<Option<String>, KickbotError> {
+        let value = self
+            .connection
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value)
+    }
+
+    pub fn set_meta(&self, key: &str, value: &str) -> Result<(), KickbotError> {
+        self.connection.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    // Kicks recorded under exactly this name, including ones not tied to a persona yet
+    pub fn name_kicks(&self, player_name: &str) -> Result<Vec<KickEntry>, KickbotError> {
+        self.query_kicks(
+            format!(
+                "SELECT * FROM {} WHERE player_name = ?1 ORDER BY kicked_at",
+                all_kicks_table()
+            )
+            .as_str(),
+            params![player_name],
+        )
+    }
+
+    // from inclusive, to exclusive
+    pub fn kicks_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<KickEntry>, KickbotError> {
+        self.query_kicks(
+            format!(
+                "SELECT * FROM {} WHERE kicked_at >= ?1 AND kicked_at < ?2 ORDER BY kicked_at",
+                all_kicks_table()
+            )
+            .as_str(),
+            params![from.timestamp(), to.timestamp()],
+        )
+    }
+
+    pub fn all_kicks(&self) -> Result<Vec<KickEntry>, KickbotError> {
+        self.query_kicks(
+            format!("SELECT * FROM {} ORDER BY kicked_at", all_kicks_table()).as_str(),
+            [],
+        )
+    }
+
+    fn query_kicks<P: rusqlite::Params>(
+        &self,
+        sql: &str,
+        params: P,
+    ) -> Result<Vec<KickEntry>, KickbotError> {
+        let mut statement = self.connection.prepare(sql)?;
+        let entries = statement
+            .query_map(params, KickEntry::from_row)?
+            .collect::<Result<Vec<KickEntry>, _>>()?;
+        Ok(entries)
+    }
+
+    // Most kicked first, as (persona id, latest name, kicks) not counting pardoned kicks or warnings
+    pub fn top_offenders(
+        &self,
+        limit: u64,
+    ) -> Result<Vec<(Option<u64>, String, u64)>, KickbotError> {
+        let mut statement = self.connection.prepare(
+            format!(
+                "SELECT persona_id, player_name, COUNT(*) AS kicks, MAX(kicked_at) FROM {}
+                 WHERE pardoned_at IS NULL AND action != 'warn'
+                 GROUP BY COALESCE(persona_id, player_name)
+                 ORDER BY kicks DESC LIMIT ?1",
+                all_kicks_table()
+            )
+            .as_str(),
+        )?;
+        let offenders = statement
+            .query_map(params![limit as i64], |row| {
+                Ok((
+                    row.get::<_, Option<i64>>(0)?.map(|id| id as u64),
+                    row.get(1)?,
+                    row.get::<_, i64>(2)? as u64,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(offenders)
+    }
+
+    // false if there's no kick with that id or it's already pardoned. Archived kicks can be
+    // pardoned too, which takes them out of the lifetime count
+    pub fn pardon(&self, id: i64) -> Result<bool, KickbotError> {
+        let mut updated = 0;
+        for table in ["kicks", "kicks_archive"] {
+            updated += self.connection.execute(
+                format!(
+                    "UPDATE {table} SET pardoned_at = ?2 WHERE id = ?1 AND pardoned_at IS NULL"
+                )
+                .as_str(),
+                params![id, Utc::now().timestamp()],
+            )?;
+        }
+        Ok(updated > 0)
+    }
+
+    // Remembers what was removed so replaying the journal doesn't put it back
+    pub fn remove(&self, id: i64) -> Result<bool, KickbotError> {
+        let mut removed = 0;
+        let transaction = self.connection.unchecked_transaction()?;
+        for table in ["kicks", "kicks_archive"] {
+            self.connection.execute(
+                format!(
+                    "INSERT INTO removed_kicks (persona_id, player_name, rule, kicked_at, game_id)
+                     SELECT persona_id, player_name, rule, kicked_at, game_id FROM {table} WHERE id = ?1"
+                )
+                .as_str(),
+                params![id],
+            )?;
+            removed += self.connection.execute(
+                format!("DELETE FROM {table} WHERE id = ?1").as_str(),
+                params![id],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(removed > 0)
+    }
+
+    // Names with kicks that aren't tied to a persona yet and haven't run out of attempts
+    pub fn unresolved_names(&self) -> Result<Vec<String>, KickbotError> {
+        let mut statement = self.connection.prepare(
+            "SELECT DISTINCT player_name FROM kicks
+             LEFT JOIN name_resolve_attempts ON name_resolve_attempts.name = kicks.player_name
+             WHERE persona_id IS NULL AND COALESCE(attempts, 0) < ?1",
+        )?;
+        let names = statement
+            .query_map(params![MAX_RESOLVE_ATTEMPTS], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(names)
+    }
+
+    // Returns how many times the name has now failed to resolve
+    pub fn record_resolve_attempt(&self, name: &str) -> Result<u64, KickbotError> {
+        let attempts: i64 = self.connection.query_row(
+            "INSERT INTO name_resolve_attempts (name, attempts, last_attempt) VALUES (?1, 1, ?2)
+             ON CONFLICT (name) DO UPDATE SET
+                attempts = attempts + 1,
+                last_attempt = excluded.last_attempt
+             RETURNING attempts",
+            params![name, Utc::now().timestamp()],
+            |row| row.get(0),
+        )?;
+        Ok(attempts as u64)
+    }
+
+    // Ties every unresolved kick under this name to the persona, returns how many were updated
+    pub fn assign_persona(&self, name: &str, persona_id: u64) -> Result<u64, KickbotError> {
+        let transaction = self.connection.unchecked_transaction()?;
+        let updated = self.connection.execute(
+            "UPDATE kicks SET persona_id = ?1 WHERE player_name = ?2 AND persona_id IS NULL",
+            params![persona_id as i64, name],
+        )?;
+        let (first_seen, last_seen): (Option<i64>, Option<i64>) = self.connection.query_row(
+            "SELECT MIN(kicked_at), MAX(kicked_at) FROM kicks WHERE player_name = ?1 AND persona_id = ?2",
+            params![name, persona_id as i64],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if let (Some(first_seen), Some(last_seen)) = (first_seen, last_seen) {
+            self.connection.execute(
+                "INSERT INTO aliases (persona_id, name, first_seen, last_seen) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (persona_id, name) DO UPDATE SET
+                    first_seen = MIN(first_seen, excluded.first_seen),
+                    last_seen = MAX(last_seen, excluded.last_seen)",
+                params![persona_id as i64, name, first_seen, last_seen],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(updated as u64)
+    }
+
+    fn import_legacy_csv(&self) -> Result<(), KickbotError> {
+        if !Path::new(LEGACY_CSV_FILE_NAME).exists() {
+            return Ok(());
+        }
+
+        let imported = self.import_csv(LEGACY_CSV_FILE_NAME)?;
+        // Already committed, renaming anyway keeps it from being imported twice
+        if !self.checkpoint()? {
+            log_info("Kick history checkpoint didn't finish after the import");
+        }
+        std::fs::rename(LEGACY_CSV_FILE_NAME, IMPORTED_CSV_FILE_NAME)?;
+        log_info(
+            format!(
+                "Imported {imported} kicks from {LEGACY_CSV_FILE_NAME} into {DATABASE_FILE_NAME}"
+            )
+            .as_str(),
+        );
+        Ok(())
+    }
+
+    // Rows are: name, then a weapon followed by its kick dates, repeated for each weapon
+    pub fn import_csv<P: AsRef<Path>>(&self, path: P) -> Result<u64, KickbotError> {
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(path)?;
+
+        let mut entries = vec![];
+        for (line, result) in csv_reader.records().enumerate() {
+            let record = match result {
+                Ok(record) => record,
+                Err(err) => {
+                    log(&KickbotError::IOError(format!(
+                        "Skipping kick history line {}, {}",
+                        line + 1,
+                        err
+                    )));
+                    continue;
+                }
+            };
+
+            let mut iter = record.iter();
+            let Some(player_name) = iter.next().filter(|name| !name.is_empty()) else {
+                continue;
+            };
+
+            let mut rule: Option<&str> = None;
+            for field in iter {
+                match NaiveDateTime::parse_from_str(field, "%Y-%m-%d %H:%M") {
+                    Ok(date) => match rule {
+                        Some(rule) => entries.push(KickEntry {
+                            id: 0,
+                            persona_id: None,
+                            player_name: player_name.to_string(),
+                            rule: rule.to_string(),
+                            reason: String::new(),
+                            kicked_at: DateTime::<Utc>::from_naive_utc_and_offset(date, Utc),
+                            server: String::new(),
+                            game_id: String::new(),
+                            map: String::new(),
+                            confidence: None,
+                            evidence_path: None,
+                            pardoned_at: None,
+                            detected_at: None,
+                            confirmed: None,
+                            action: KickAction::Kick,
+                        }),
+                        None => log(&KickbotError::IOError(format!(
+                            "Kick history line {} has a date before any weapon, skipping it",
+                            line + 1
+                        ))),
+                    },
+                    Err(_) => rule = Some(field),
+                }
+            }
+        }
+
+        let transaction = self.connection.unchecked_transaction()?;
+        for entry in entries.iter() {
+            self.insert_kick(entry)?;
+        }
+        transaction.commit()?;
+        Ok(entries.len() as u64)
+    }
+}
+
+fn migrate(connection: &Connection) -> Result<(), KickbotError> {
+    let version: usize =
+        connection.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))? as usize;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        connection.execute_batch(migration)?;
+        connection.pragma_update(None, "user_version", (index + 1) as i64)?;
+    }
+    Ok(())
+}
+
+pub fn log_retention(removed: u64, policy: &HistoryPolicy) {
+    if removed == 0 {
+        return;
+    }
+    let action = match policy.retention_action {
+        RetentionAction::Archive => "Archived",
+        RetentionAction::Delete => "Deleted",
+    };
+    log_info(format!("{action} {removed} kicks past the retention limit").as_str());
+}
+
+// Roster names have the platoon tag in front, the identity lookup only wants the name
+pub fn strip_platoon_tag(name: &str) -> &str {
+    match name.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((_, name)) if !name.is_empty() => name,
+        _ => name,
+    }
+}
+
+// Ties kicks recorded by name to a persona. Names that don't resolve are kept and retried next
+// start, up to MAX_RESOLVE_ATTEMPTS times
+pub async fn migrate_name_keyed_kicks(api: &BF1Api, kick_record: &Mutex<HistoryStore>) {
+    let names = match kick_record.lock().await.unresolved_names() {
+        Ok(names) => names,
+        Err(err) => {
+            log(&err);
+            return;
+        }
+    };
+    if names.is_empty() {
+        return;
+    }
+
+    log_info(format!("Resolving {} player names in kick history", names.len()).as_str());
+    let mut resolved = 0;
+    for name in names.iter() {
+        let persona_id = match api
+            .get_player_persona_by_name(strip_platoon_tag(name))
+            .await
+        {
+            Ok(persona_id) => persona_id,
+            // Network trouble says nothing about the name, only a failed lookup counts
+            Err(err) if err.is_transient() => {
+                log(&err);
+                continue;
+            }
+            Err(err) => {
+                log(&err);
+                match kick_record.lock().await.record_resolve_attempt(name) {
+                    Ok(attempts) if attempts >= MAX_RESOLVE_ATTEMPTS as u64 => log_info(
+                        format!("Giving up on resolving {name} after {attempts} attempts").as_str(),
+                    ),
+                    Ok(_) => {}
+                    Err(err) => log(&err),
+                }
+                continue;
+            }
+        };
+        match kick_record.lock().await.assign_persona(name, persona_id) {
+            Ok(_) => resolved += 1,
+            Err(err) => log(&err),
+        }
+    }
+    log_info(
+        format!(
+            "Resolved {resolved} of {} player names in kick history",
+            names.len()
+        )
+        .as_str(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // In memory database, the journal still needs a real file
+    fn open_store(name: &str) -> HistoryStore {
+        let journal_path = std::env::temp_dir().join(format!(
+            "vgkickbot_{}_{name}_journal.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&journal_path);
+        HistoryStore::open_path(":memory:", journal_path).unwrap()
+    }
+
+    fn kick(persona_id: u64, player_name: &str, days_ago: i64) -> KickEntry {
+        let mut entry = KickEntry::new(
+            persona_id,
+            player_name,
+            "SMG",
+            "No SMG",
+            "![VG]",
+            "123",
+            &KickContext::default(),
+        );
+        entry.kicked_at = Utc::now() - TimeDelta::days(days_ago);
+        entry
+    }
+
+    #[test]
+    fn imports_csv_history() {
+        let store = open_store("import");
+        let csv_path =
+            std::env::temp_dir().join(format!("vgkickbot_{}_kick_history.csv", std::process::id()));
+        std::fs::write(
+            &csv_path,
+            "[TAG]Player,SMG,2024-01-01 10:00,2024-01-02 11:30,Shotgun,2024-01-03 12:00\nOther,SMG,2024-02-01 09:15\n",
+        )
+        .unwrap();
+
+        assert_eq!(store.import_csv(&csv_path).unwrap(), 4);
+        let kicks = store.name_kicks("[TAG]Player").unwrap();
+        assert_eq!(kicks.len(), 3);
+        assert_eq!(kicks[2].rule, "Shotgun");
+        assert!(kicks.iter().all(|kick| kick.persona_id.is_none()));
+        assert_eq!(store.unresolved_names().unwrap().len(), 2);
+
+        assert_eq!(store.assign_persona("[TAG]Player", 42).unwrap(), 3);
+        assert_eq!(store.count_kicks(42, None).unwrap(), 3);
+        assert_eq!(store.unresolved_names().unwrap(), vec!["Other".to_string()]);
+        std::fs::remove_file(csv_path).unwrap();
+    }
+
+    #[test]
+    fn finds_persona_by_alias() {
+        let store = open_store("alias");
+        store.record_kick(&kick(1, "[TAG]Player", 2)).unwrap();
+        store.record_kick(&kick(1, "Renamed", 1)).unwrap();
+
+        assert_eq!(store.persona_for_name("[TAG]Player").unwrap(), Some(1));
+        // Without the platoon tag
+        assert_eq!(store.persona_for_name("Player").unwrap(), Some(1));
+        assert_eq!(store.persona_for_name("Play").unwrap(), None);
+        assert_eq!(
+            store.names_for_persona(1).unwrap(),
+            vec!["Renamed".to_string(), "[TAG]Player".to_string()]
+        );
+    }
+
+    #[test]
+    fn pardoned_kicks_dont_count() {
+        let store = open_store("pardon");
+        let first = store.record_kick(&kick(1, "Player", 10)).unwrap();
+        store.record_kick(&kick(1, "Player", 1)).unwrap();
+        assert_eq!(store.count_kicks(1, None).unwrap(), 2);
+        assert_eq!(
+            store
+                .count_kicks(1, Some(Utc::now() - TimeDelta::days(5)))
+                .unwrap(),
+            1
+        );
+
+        assert!(store.pardon(first).unwrap());
+        assert!(!store.pardon(first).unwrap());
+        assert_eq!(store.count_kicks(1, None).unwrap(), 1);
+        assert_eq!(store.player_kicks(1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn archived_kicks_stay_visible() {
+        let store = open_store("archive");
+        let archived = store.record_kick(&kick(1, "Player", 60)).unwrap();
+        let policy = HistoryPolicy {
+            retention: Some(TimeDelta::days(30)),
+            ..HistoryPolicy::default()
+        };
+        store.apply_retention(&policy).unwrap();
+
+        // The kicks table is empty now, the next id still can't clash with the archived one
+        let active = store.record_kick(&kick(1, "Player", 1)).unwrap();
+        assert_ne!(active, archived);
+        assert_eq!(store.player_kicks(1).unwrap().len(), 2);
+        assert_eq!(store.all_kicks().unwrap().len(), 2);
+        assert_eq!(store.top_offenders(1).unwrap()[0].2, 2);
+
+        assert!(store.pardon(archived).unwrap());
+        assert_eq!(store.count_lifetime_kicks(1).unwrap(), 1);
+        assert!(store.remove(archived).unwrap());
+        assert_eq!(store.all_kicks().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn compact_empties_the_journal() {
+        let store = open_store("compact");
+        store.record_kick(&kick(1, "Player", 1)).unwrap();
+        assert!(std::fs::metadata(&store.journal_path).unwrap().len() > 0);
+
+        store.compact().unwrap();
+        assert_eq!(std::fs::metadata(&store.journal_path).unwrap().len(), 0);
+        assert_eq!(store.count_kicks(1, None).unwrap(), 1);
+    }
+
+    #[test]
+    fn removed_kicks_stay_removed_after_replay() {
+        let store = open_store("removed");
+        let id = store.record_kick(&kick(1, "Player", 1)).unwrap();
+        assert!(store.remove(id).unwrap());
+
+        store.replay_journal().unwrap();
+        assert!(store.all_kicks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stops_resolving_after_max_attempts() {
+        let store = open_store("resolve");
+        let mut entry = kick(1, "Gone", 1);
+        entry.persona_id = None;
+        store.record_kick(&entry).unwrap();
+
+        for attempt in 1..MAX_RESOLVE_ATTEMPTS as u64 {
+            assert_eq!(store.record_resolve_attempt("Gone").unwrap(), attempt);
+            assert_eq!(store.unresolved_names().unwrap(), vec!["Gone".to_string()]);
+        }
+        store.record_resolve_attempt("Gone").unwrap();
+        assert!(store.unresolved_names().unwrap().is_empty());
+    }
+
+    #[test]
+    fn retention_archives_old_kicks() {
+        let store = open_store("retention");
+        store.record_kick(&kick(1, "Player", 60)).unwrap();
+        store.record_kick(&kick(1, "Player", 1)).unwrap();
+        let policy = HistoryPolicy {
+            offence_decay: None,
+            retention: Some(TimeDelta::days(30)),
+            retention_action: RetentionAction::Archive,
+        };
+
+        assert_eq!(store.apply_retention(&policy).unwrap(), 1);
+        assert_eq!(store.count_kicks(1, None).unwrap(), 1);
+        assert_eq!(store.count_lifetime_kicks(1).unwrap(), 2);
+
+        let policy = HistoryPolicy {
+            retention: Some(TimeDelta::hours(1)),
+            retention_action: RetentionAction::Delete,
+            ..policy
+        };
+        assert_eq!(store.apply_retention(&policy).unwrap(), 1);
+        assert_eq!(store.count_lifetime_kicks(1).unwrap(), 1);
+
+        let policy = HistoryPolicy {
+            retention: Some(TimeDelta::zero()),
+            ..policy
+        };
+        assert!(store.apply_retention(&policy).is_err());
+    }
+}
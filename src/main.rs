@@ -1,25 +1,42 @@
 extern crate core;
 
 mod api;
+mod balance;
 mod botstatus;
+mod cli;
 mod config;
 mod console;
 mod cycle;
 mod discord;
 mod errors;
+mod history;
+mod reason;
 mod recognition;
+mod rotation;
+mod statistics;
 
 use crate::api::bf1api::server::ServerDetails;
 use crate::api::bf1api::BF1Api;
+use crate::api::errors::BF1ApiError;
+use crate::balance::BalanceMonitor;
 use crate::botstatus::{BotStatus, StatusTypes};
-use crate::config::{load_kick_history_record, save_kick_record, Config, PlayerKickHistoryRecord};
-use crate::console::{clear, log, update_status};
+use crate::config::{load_round_state, save_round_state, save_server_guid, Config, ServerConfig};
+use crate::console::{clear, log, log_info, update_status};
 use crate::cycle::{execute, Executors, GameState, SpecCycle};
-use crate::discord::{announce_bot_crashed, announce_monitoring, announce_shutdown};
+use crate::discord::{
+    announce_bot_crashed, announce_monitoring, announce_shutdown, announce_weekly_summary,
+};
 use crate::errors::KickbotError;
 use crate::errors::KickbotError::ScreenshotError;
+use crate::history::{
+    log_retention, migrate_name_keyed_kicks, HistoryStore, JOURNAL_COMPACT_INTERVAL,
+};
 use crate::recognition::kick_player::kick_player;
 use crate::recognition::model::Classifier;
+use crate::rotation::RotationScheduler;
+use crate::statistics::{
+    due_weekly_report, mark_weekly_summary_sent, WEEKLY_SUMMARY_CHECK_INTERVAL,
+};
 use chrono::{DateTime, Utc};
 use crossterm::event::{poll, read, Event};
 use enigo::Direction::{Press, Release};
@@ -28,9 +45,10 @@ use serenity::all::MemberAction::Kick;
 use std::collections::HashSet;
 use std::ffi::CString;
 use std::io::ErrorKind;
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
 use std::path::Path;
 use std::process::{exit, Command};
+use std::ptr;
 use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
 use std::{env, io, thread};
@@ -56,10 +74,12 @@ static BOT_STATS: OnceLock<Arc<RwLock<BotStats>>> = OnceLock::new();
 
 static CONFIG: OnceCell<Config> = OnceCell::const_new();
 
-static KICK_RECORD: OnceLock<Arc<Mutex<PlayerKickHistoryRecord>>> = OnceLock::new();
+static KICK_RECORD: OnceLock<Arc<Mutex<HistoryStore>>> = OnceLock::new();
 
 static mut DO_EXIT_ANNOUNCEMENT: bool = true;
 
+static BF1_API: OnceCell<BF1Api> = OnceCell::const_new();
+
 unsafe fn restart_bot() -> io::Result<()> {
     let current_exe = env::current_exe()?;
     Command::new(current_exe).args(&["0"]).status()?;
@@ -173,21 +193,223 @@ unsafe extern "system" fn close_handler(_: u32) -> windows_core::BOOL {
                 .expect("Something went wrong announcing shutdown");
             }
 
-            let _ = save_kick_record(KICK_RECORD.get().unwrap().lock().await.deref()).inspect_err(
-                |err| {
-                    panic!(
-                        "Something went wrong saving kick record, {}",
-                        err.to_string()
-                    )
-                },
-            );
+            if let Some(kick_record) = KICK_RECORD.get() {
+                let _ = kick_record.lock().await.compact().inspect_err(log);
+            }
         });
 
     windows_core::BOOL(1)
 }
 
+#[derive(Clone)]
+struct MonitoredServer {
+    config: &'static ServerConfig,
+    details: Arc<Mutex<ServerDetails>>,
+    game_state: Arc<RwLock<GameState>>,
+    updated: Arc<Mutex<bool>>,
+    // Set when the gameId changes, the main loop moves the game client over
+    rejoin: Arc<Mutex<bool>>,
+}
+
+impl MonitoredServer {
+    async fn new(api: &BF1Api, config: &'static ServerConfig) -> Result<Self, BF1ApiError> {
+        let details = api.get_server(&config.name, config.guid.as_deref()).await?;
+        if let Err(err) = save_server_guid(&config.name, &details.guid) {
+            log(&err);
+        }
+
+        // Pick up where the last process left off if it's still the same round
+        let mut game_state = GameState::default();
+        if let Some(round_state) = load_round_state(&config.name) {
+            if game_state.restore(round_state, &details.game_id) {
+                log_info(
+                    format!(
+                        "Restored {} pending kicks for {}",
+                        game_state.pending_kick_players.len(),
+                        config.name
+                    )
+                    .as_str(),
+                );
+            }
+        }
+
+        Ok(MonitoredServer {
+            config,
+            details: Arc::new(Mutex::new(details)),
+            game_state: Arc::new(RwLock::new(game_state)),
+            updated: Arc::new(Mutex::new(false)),
+            rejoin: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    // Looks the server up again, a restart gives it a new gameId but keeps the guid
+    async fn resolve(
+        &self,
+        api: &BF1Api,
+        current_guid: &str,
+    ) -> Result<ServerDetails, BF1ApiError> {
+        let guid = match current_guid.is_empty() {
+            true => self.config.guid.as_deref(),
+            false => Some(current_guid),
+        };
+        api.get_server(&self.config.name, guid).await
+    }
+}
+
+// Re-resolve the server every minute, or straight away if a refresh fails
+const RESOLVE_EVERY_REFRESHES: u32 = 6;
+
+// Restarts the game client onto game_id, the bot restarts along with it
+async fn rejoin_server(game_id: String) {
+    kill_bf1();
+    while bf1_running() {
+        sleep(Duration::from_secs(1)).await;
+    }
+    launch_bf1_join_server(CONFIG.get().unwrap().bf1_path.clone(), game_id);
+    focus_bf1_once_running().await;
+}
+
+// Refreshes a server every 10 seconds, view is only passed for the spectated server
+async fn refresh_server(
+    server: MonitoredServer,
+    view: Option<(Arc<RwLock<BotStatus>>, Arc<Mutex<console::Console>>)>,
+) {
+    let api = BF1_API.get().unwrap();
+    let mut balance_monitor = BalanceMonitor::default();
+    let mut rotation_scheduler = RotationScheduler::default();
+    let mut refreshes_since_resolve = 0;
+    loop {
+        sleep(Duration::from_secs(10)).await;
+
+        let mut server_details = server.details.lock().await;
+        let mut refresh_failed = false;
+        if let Err(err) = server_details.update_players(api).await {
+            log(&err);
+            refresh_failed = true;
+        }
+        let gameid = Some(server_details.game_id.clone());
+        if let Err(err) = server_details.update_server_details(api, gameid).await {
+            log(&err);
+            refresh_failed = true;
+        }
+
+        refreshes_since_resolve += 1;
+        if refresh_failed || refreshes_since_resolve >= RESOLVE_EVERY_REFRESHES {
+            refreshes_since_resolve = 0;
+            match server.resolve(api, &server_details.guid).await {
+                Ok(resolved) if resolved.game_id != server_details.game_id => {
+                    log_info(
+                        format!(
+                            "{} restarted, gameId changed from {} to {}",
+                            resolved.name, server_details.game_id, resolved.game_id
+                        )
+                        .as_str(),
+                    );
+                    *server_details = resolved;
+                    *server.game_state.write().await = GameState::default();
+
+                    if view.is_some() {
+                        *server.rejoin.lock().await = true;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => log(&err),
+            }
+        }
+
+        if let Some(server_id) = server_details.server_id {
+            api.lift_expired_temp_bans(server_id).await;
+        }
+
+        // Moving players or switching maps off a stale roster does more harm than waiting
+        if !refresh_failed {
+            balance_monitor
+                .check(api, server.config, server_details.deref())
+                .await;
+            rotation_scheduler
+                .check(api, server.config, server_details.deref())
+                .await;
+        }
+
+        if let Some((bot_status, console)) = &view {
+            if server_details.player_count() < server.config.min_players_for_kick as usize {
+                bot_status.write().await.status = StatusTypes::Disabled;
+            } else {
+                if bot_status.read().await.status == StatusTypes::Disabled {
+                    bot_status.write().await.status = StatusTypes::WaitingForBF1;
+                }
+            }
+
+            let (width, height) = crossterm::terminal::size().unwrap();
+            console
+                .lock()
+                .await
+                .update_static_area(
+                    server_details.deref(),
+                    bot_status.read().await.deref(),
+                    BOT_STATS.get().unwrap().read().await.deref(),
+                    width,
+                    height,
+                )
+                .await;
+        }
+
+        let mut server_updated_writer = server.updated.lock().await;
+        *server_updated_writer = true;
+
+        // Other tasks read the details too, kicks work from a copy
+        let server_details = {
+            let snapshot = server_details.clone();
+            drop(server_details);
+            snapshot
+        };
+
+        // Copied out, kick_player needs to write to the game state
+        let pending_kick_players = {
+            let mut game_state = server.game_state.write().await;
+            game_state.expire_pending_kicks();
+            game_state.pending_kick_players.clone()
+        };
+        for (player, weapon) in pending_kick_players.iter() {
+            kick_player(
+                api,
+                CONFIG.get().unwrap(),
+                server.config,
+                KICK_RECORD.get().unwrap().clone(),
+                player,
+                weapon.name.clone(),
+                weapon.category.clone(),
+                None,
+                None,
+                weapon.detected_at,
+                server.game_state.clone(),
+                &server_details,
+                BOT_STATS.get().unwrap().clone(),
+                true,
+            )
+            .await
+        }
+
+        let round_state = server
+            .game_state
+            .read()
+            .await
+            .to_round_state(&server_details.game_id);
+        if let Err(err) = save_round_state(&server.config.name, round_state) {
+            log(&err);
+        }
+    }
+}
+
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && cli::is_command(&args[1]) {
+        return tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(cli::run(&args[1..]));
+    }
+
     // Arg 1: Announce monitoring yes/no
     let mut should_announce_monitor = true;
     if args.len() >= 2 {
@@ -205,13 +427,17 @@ fn main() -> io::Result<()> {
 }
 
 async fn main_thread(should_announce_monitor: bool) -> io::Result<()> {
-    let config = match Config::read_config("config.json").await {
+    let config = match Config::read_config("config.json") {
         Ok(config) => config,
         Err(err) => {
             eprintln!("Error reading config, {err}");
             return Err(io::Error::new(ErrorKind::InvalidData, err));
         }
     };
+    if let Err(err) = config.connect_webhooks().await {
+        eprintln!("Error connecting to Discord, {err}");
+        return Err(io::Error::new(ErrorKind::InvalidData, err));
+    }
 
     BOT_STATS
         .set(Arc::new(RwLock::new(BotStats {
@@ -227,25 +453,40 @@ async fn main_thread(should_announce_monitor: bool) -> io::Result<()> {
         last_valid_name: None,
     }));
 
-    if should_announce_monitor {
-        announce_monitoring(
-            &config.monitoring_webhook,
-            BOT_STATS.get().unwrap().read().await.start_time,
-        )
-        .await
-        .inspect_err(log)?;
-    }
-
-    let bf1_api = BF1Api::new().await?;
-
-    static BF1_API: OnceCell<BF1Api> = OnceCell::const_new();
+    let bf1_api = BF1Api::new(config.api_policy.clone(), config.roster_policy.clone()).await?;
 
     let display_names = bf1_api
-        .get_display_names_by_persona_ids(vec![bf1_api.persona_id().as_str()])
+        .get_display_names_by_persona_ids(vec![bf1_api.persona_id()])
         .await?;
 
     let user_name = display_names[0].clone();
-    let server = Arc::new(Mutex::new(bf1_api.get_server_by_name("![VG]").await?));
+
+    CONFIG.set(config).unwrap();
+    BF1_API.set(bf1_api).unwrap();
+    let config = CONFIG.get().unwrap();
+    let api = BF1_API.get().unwrap();
+
+    let mut servers = Vec::with_capacity(config.servers.len());
+    for server_config in config.servers.iter() {
+        let server = MonitoredServer::new(api, server_config).await?;
+        if should_announce_monitor {
+            announce_monitoring(
+                &server_config.monitoring_webhook,
+                BOT_STATS.get().unwrap().read().await.start_time,
+                server.details.lock().await.deref(),
+            )
+            .await
+            .inspect_err(log)?;
+        }
+        servers.push(server);
+    }
+
+    // The server the game client spectates, the rest are only monitored through the API
+    let spectated = servers
+        .iter()
+        .find(|server| ptr::eq(server.config, config.spectated_server()))
+        .unwrap()
+        .clone();
 
     let console = Arc::new(Mutex::new(console::Console::new(user_name)));
 
@@ -254,7 +495,7 @@ async fn main_thread(should_announce_monitor: bool) -> io::Result<()> {
         .lock()
         .await
         .update_static_area(
-            server.lock().await.deref(),
+            spectated.details.lock().await.deref(),
             bot_status.read().await.deref(),
             BOT_STATS.get().unwrap().read().await.deref(),
             width,
@@ -262,24 +503,68 @@ async fn main_thread(should_announce_monitor: bool) -> io::Result<()> {
         )
         .await;
 
-    CONFIG.set(config).unwrap();
-    BF1_API.set(bf1_api).unwrap();
     KICK_RECORD
-        .set(Arc::new(Mutex::new(load_kick_history_record()?)))
+        .set(Arc::new(Mutex::new(HistoryStore::open()?)))
         .unwrap();
+    tokio::spawn(migrate_name_keyed_kicks(
+        api,
+        KICK_RECORD.get().unwrap().deref(),
+    ));
+    tokio::spawn(async {
+        let history_policy = &CONFIG.get().unwrap().history_policy;
+        loop {
+            {
+                let kick_record = KICK_RECORD.get().unwrap().lock().await;
+                // Compacted first so retention can't be undone by replaying the journal
+                let result = kick_record
+                    .compact()
+                    .and_then(|_| kick_record.apply_retention(history_policy));
+                match result {
+                    Ok(removed) => log_retention(removed, history_policy),
+                    Err(err) => log(&err),
+                }
+            }
+            sleep(JOURNAL_COMPACT_INTERVAL).await;
+        }
+    });
+    if config.weekly_summary {
+        tokio::spawn(async {
+            let kick_record = KICK_RECORD.get().unwrap();
+            loop {
+                let report = due_weekly_report(kick_record.lock().await.deref());
+                match report {
+                    Ok(Some(report)) => {
+                        match announce_weekly_summary(
+                            &CONFIG.get().unwrap().monitoring_webhook,
+                            &report,
+                        )
+                        .await
+                        {
+                            // Tried again next check if it didn't go through
+                            Ok(_) => {
+                                if let Err(err) = mark_weekly_summary_sent(
+                                    kick_record.lock().await.deref(),
+                                    report.to,
+                                ) {
+                                    log(&err);
+                                }
+                            }
+                            Err(err) => log(&err),
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => log(&err),
+                }
+                sleep(WEEKLY_SUMMARY_CHECK_INTERVAL).await;
+            }
+        });
+    }
 
     let spec_cycle = Arc::new(Mutex::new(SpecCycle::new()));
-    let game_state = Arc::new(RwLock::new(GameState::default()));
     let executors = Arc::new(Mutex::new(Executors::new(10)));
 
-    let server_updated: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
-
-    let server_clone = server.clone();
+    let server_clone = spectated.details.clone();
     let bot_status_clone = bot_status.clone();
-    let server_updated_clone = server_updated.clone();
-
-    let server_clone_2 = server.clone();
-    let bot_status_clone_2 = bot_status.clone();
     let console_clone = console.clone();
 
     tokio::spawn(async move {
@@ -289,8 +574,8 @@ async fn main_thread(should_announce_monitor: bool) -> io::Result<()> {
                     .lock()
                     .await
                     .update_static_area(
-                        server_clone_2.lock().await.deref(),
-                        bot_status_clone_2.read().await.deref(),
+                        server_clone.lock().await.deref(),
+                        bot_status_clone.read().await.deref(),
                         BOT_STATS.get().unwrap().read().await.deref(),
                         width,
                         height,
@@ -300,68 +585,18 @@ async fn main_thread(should_announce_monitor: bool) -> io::Result<()> {
         }
     });
 
-    let game_state_clone = game_state.clone();
-
-    tokio::spawn(async move {
-        loop {
-            sleep(Duration::from_secs(10)).await;
-
-            let mut server_details = server_clone.lock().await;
-            if let Err(err) = server_details.update_players(BF1_API.get().unwrap()).await {
-                log(&err);
-            }
-            let gameid = Some(server_details.game_id.clone());
-            if let Err(err) = server_details
-                .update_server_details(BF1_API.get().unwrap(), gameid)
-                .await
-            {
-                log(&err);
-            }
-
-            if server_details.player_count() < CONFIG.get().unwrap().min_players_for_kick as usize {
-                bot_status_clone.write().await.status = StatusTypes::Disabled;
-            } else {
-                if bot_status_clone.read().await.status == StatusTypes::Disabled {
-                    bot_status_clone.write().await.status = StatusTypes::WaitingForBF1;
-                }
-            }
-
-            let (width, height) = crossterm::terminal::size().unwrap();
-            console
-                .lock()
-                .await
-                .update_static_area(
-                    server_details.deref(),
-                    bot_status_clone.read().await.deref(),
-                    BOT_STATS.get().unwrap().read().await.deref(),
-                    width,
-                    height,
-                )
-                .await;
-
-            let mut server_updated_writer = server_updated_clone.lock().await;
-            *server_updated_writer = true;
-
-            let game_state_read = game_state_clone.read().await;
-            for (player, weapon) in game_state_read.pending_kick_players.iter() {
-                kick_player(
-                    BF1_API.get().unwrap(),
-                    CONFIG.get().unwrap(),
-                    KICK_RECORD.get().unwrap().clone(),
-                    player,
-                    weapon.name.clone(),
-                    weapon.category.clone(),
-                    game_state_clone.clone(),
-                    server_details.deref(),
-                    BOT_STATS.get().unwrap().clone(),
-                    true,
-                )
-                .await
-            }
-        }
-    });
+    for server in servers {
+        let view = match ptr::eq(server.config, spectated.config) {
+            true => Some((bot_status.clone(), console.clone())),
+            false => None,
+        };
+        tokio::spawn(refresh_server(server, view));
+    }
 
-    let server_cached: ServerDetails = server.clone().lock().await.clone();
+    let server = spectated.details.clone();
+    let game_state = spectated.game_state.clone();
+    let server_updated = spectated.updated.clone();
+    let server_cached: ServerDetails = server.lock().await.clone();
 
     let classifier = Arc::new(Classifier::new());
 
@@ -379,6 +614,10 @@ async fn main_thread(should_announce_monitor: bool) -> io::Result<()> {
     // If we crash/don't have BF1, invalidate the last player name
     // So if we don't have a valid last player name then we know not to send a crash message if we don't read one
     loop {
+        if std::mem::take(spectated.rejoin.lock().await.deref_mut()) {
+            rejoin_server(server.lock().await.game_id.clone()).await;
+        }
+
         let do_cycle = async || {
             if let Ok(window) = active_win_pos_rs::get_active_window() {
                 if window.title != "Battlefield™ 1" {
@@ -412,6 +651,7 @@ async fn main_thread(should_announce_monitor: bool) -> io::Result<()> {
                     if let Err(err) = execute(
                         BF1_API.get().unwrap(),
                         CONFIG.get().unwrap(),
+                        spectated.config,
                         KICK_RECORD.get().unwrap().clone(),
                         game_state.clone(),
                         executors.clone(),
@@ -435,20 +675,10 @@ async fn main_thread(should_announce_monitor: bool) -> io::Result<()> {
             let bot_status_read = bot_status.read().await;
 
             if bot_status_read.status == StatusTypes::Crashed {
-                if let Err(err) =
-                    announce_bot_crashed(&CONFIG.get().unwrap().monitoring_webhook).await
-                {
+                if let Err(err) = announce_bot_crashed(&spectated.config.monitoring_webhook).await {
                     log(&err);
                 }
-                kill_bf1();
-                while bf1_running() {
-                    sleep(Duration::from_secs(1)).await;
-                }
-                launch_bf1_join_server(
-                    CONFIG.get().unwrap().bf1_path.clone(),
-                    server.lock().await.clone().game_id,
-                );
-                focus_bf1_once_running().await;
+                rejoin_server(server.lock().await.game_id.clone()).await;
 
                 drop(bot_status_read);
                 bot_status.write().await.status = StatusTypes::WaitingForBF1;
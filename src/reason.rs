@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+// Placeholders that can be used in a reason template
+const PLACEHOLDERS: [&str; 4] = ["{weapon}", "{player}", "{offences}", "{rules_url}"];
+
+// Anything else is stripped, the game doesn't show most symbols or non ASCII characters
+const ALLOWED_SYMBOLS: &str = " .,!?:;-_()[]/'#+&@";
+
+#[derive(Debug, Clone)]
+pub struct ReasonTemplate {
+    pub reason: String,
+    // Used once a player has been kicked repeat_from times, including this kick
+    pub repeat_reason: Option<String>,
+}
+
+// Longest kick reason the game shows, anything past it is cut off
+pub const MAX_REASON_LENGTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct KickReasons {
+    pub default: ReasonTemplate,
+    // Keyed by the weapon/vehicle pretty name from the banned lists
+    pub rules: HashMap<String, ReasonTemplate>,
+    pub repeat_from: u64,
+    pub rules_url: String,
+    pub max_length: usize,
+}
+
+impl Default for KickReasons {
+    fn default() -> Self {
+        KickReasons {
+            default: ReasonTemplate {
+                reason: "No {weapon}, Read Rules".to_string(),
+                repeat_reason: None,
+            },
+            rules: HashMap::new(),
+            repeat_from: 2,
+            rules_url: String::new(),
+            max_length: MAX_REASON_LENGTH,
+        }
+    }
+}
+
+impl KickReasons {
+    fn template(&self, weapon: &str, offences: u64) -> &str {
+        let template = self.rules.get(weapon).unwrap_or(&self.default);
+        let repeat_reason = template
+            .repeat_reason
+            .as_ref()
+            .or(self.default.repeat_reason.as_ref());
+        match repeat_reason {
+            Some(repeat_reason) if offences >= self.repeat_from => repeat_reason,
+            _ => &template.reason,
+        }
+    }
+
+    // The reason before it's made safe to send
+    pub fn fill(&self, weapon: &str, player: &str, offences: u64) -> String {
+        self.fill_template(self.template(weapon, offences), weapon, player, offences)
+    }
+
+    pub fn fill_template(
+        &self,
+        template: &str,
+        weapon: &str,
+        player: &str,
+        offences: u64,
+    ) -> String {
+        template
+            .replace("{weapon}", weapon)
+            .replace("{player}", player)
+            .replace("{offences}", offences.to_string().as_str())
+            .replace("{rules_url}", self.rules_url.as_str())
+    }
+
+    // offences includes the kick this reason is for
+    pub fn render(&self, weapon: &str, player: &str, offences: u64) -> String {
+        sanitize(
+            self.fill(weapon, player, offences).as_str(),
+            self.max_length,
+        )
+    }
+
+    // Same as render but with a template from somewhere else, e.g. an escalation step
+    pub fn render_template(
+        &self,
+        template: &str,
+        weapon: &str,
+        player: &str,
+        offences: u64,
+    ) -> String {
+        sanitize(
+            self.fill_template(template, weapon, player, offences)
+                .as_str(),
+            self.max_length,
+        )
+    }
+
+    // First offence and repeat reasons for every template, marking any that get cut down
+    pub fn preview(&self) -> Vec<String> {
+        let mut weapons: Vec<&str> = self.rules.keys().map(String::as_str).collect();
+        weapons.sort();
+        weapons.insert(0, "Weapon");
+
+        let mut lines = vec![];
+        for weapon in weapons {
+            for offences in [1, self.repeat_from.max(2)] {
+                let filled = self.fill(weapon, "PlayerName", offences);
+                let rendered = sanitize(filled.as_str(), self.max_length);
+                let note = match rendered == filled {
+                    true => "",
+                    false => " (shortened or stripped)",
+                };
+                lines.push(format!(
+                    "{} x{}: \"{}\"{}",
+                    weapon, offences, rendered, note
+                ));
+            }
+        }
+        lines
+    }
+}
+
+// Checks every {...} in a template is a known placeholder
+pub fn validate_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("Unclosed placeholder in \"{template}\""));
+        };
+        let placeholder = &rest[start..start + end + 1];
+        if !PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!(
+                "Unknown placeholder {placeholder} in \"{template}\", expected one of {}",
+                PLACEHOLDERS.join(", ")
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+pub fn sanitize(reason: &str, max_length: usize) -> String {
+    reason
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || ALLOWED_SYMBOLS.contains(*c))
+        .take(max_length)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_strips_symbols_and_cuts_to_length() {
+        assert_eq!(sanitize("No  SMG™ ♥ here", 32), "No  SMG  here");
+        assert_eq!(sanitize("No {weapon}", 32), "No weapon");
+        assert_eq!(sanitize("Read the rules", 8), "Read the");
+        assert_eq!(sanitize("Rules ", 6), "Rules");
+    }
+
+    #[test]
+    fn validate_template_checks_placeholders() {
+        assert!(validate_template("No {weapon}, {player} x{offences} {rules_url}").is_ok());
+        assert!(validate_template("No placeholders").is_ok());
+        assert!(validate_template("No {gun}").is_err());
+        assert!(validate_template("No {weapon").is_err());
+    }
+
+    #[test]
+    fn repeat_reason_from_repeat_from() {
+        let reasons = KickReasons {
+            default: ReasonTemplate {
+                reason: "No {weapon}".to_string(),
+                repeat_reason: Some("{weapon} again x{offences}".to_string()),
+            },
+            ..KickReasons::default()
+        };
+        assert_eq!(reasons.render("SMG", "Player", 1), "No SMG");
+        assert_eq!(reasons.render("SMG", "Player", 2), "SMG again x2");
+    }
+}
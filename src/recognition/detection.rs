@@ -158,7 +158,7 @@ pub fn detect(
     config: &Config,
     ocr: OCR,
     classifier: &Classifier,
-) -> Result<(OCR, Option<String>, Option<WeaponClasses>), KickbotError> {
+) -> Result<(OCR, Option<String>, Option<WeaponClasses>, Option<f32>), KickbotError> {
     let weapon_icon_image = screenshot.crop_image(config.weapon_icon_box)?;
     let (probability, category) = classifier.infer(&weapon_icon_image)?;
 
@@ -170,13 +170,18 @@ pub fn detect(
         // No icon detected, just try text
         (ocr, maybe_banned_weapon) = detector.smg_slot1(ocr, config, &screenshot)?;
         if maybe_banned_weapon.is_some() {
-            return Ok((ocr, maybe_banned_weapon, Some(WeaponClasses::SMG08)));
+            return Ok((ocr, maybe_banned_weapon, Some(WeaponClasses::SMG08), None));
         }
 
         (ocr, maybe_banned_weapon) =
             detector.heavy_bomber_slot1_slot2(ocr, config, &screenshot, false)?;
         if maybe_banned_weapon.is_some() {
-            return Ok((ocr, maybe_banned_weapon, Some(WeaponClasses::HeavyBomber)));
+            return Ok((
+                ocr,
+                maybe_banned_weapon,
+                Some(WeaponClasses::HeavyBomber),
+                None,
+            ));
         }
 
         if config.are_similar(
@@ -186,11 +191,11 @@ pub fn detect(
         ) {
             (ocr, maybe_banned_weapon) = detector.lmg_slot2(ocr, config, &screenshot)?;
             if maybe_banned_weapon.is_some() {
-                return Ok((ocr, maybe_banned_weapon, Some(WeaponClasses::LMG)));
+                return Ok((ocr, maybe_banned_weapon, Some(WeaponClasses::LMG), None));
             }
         }
 
-        return Ok((ocr, None, None));
+        return Ok((ocr, None, None, None));
     }
 
     let (ocr, maybe_banned_weapon) = match category {
@@ -207,8 +212,8 @@ pub fn detect(
     };
 
     if maybe_banned_weapon.is_some() {
-        return Ok((ocr, maybe_banned_weapon, Some(category)));
+        return Ok((ocr, maybe_banned_weapon, Some(category), Some(probability)));
     }
 
-    Ok((ocr, None, None))
+    Ok((ocr, None, None, None))
 }
@@ -1,23 +1,32 @@
+use crate::api::bf1api::models::ServerPlayer;
 use crate::api::bf1api::server::ServerDetails;
 use crate::api::bf1api::BF1Api;
-use crate::config::{Config, PlayerKickHistoryRecord};
+use crate::config::{Config, ServerConfig};
+use crate::console::log_info;
 use crate::cycle::{GameState, RecordWeapon};
 use crate::discord::DiscordWebhook;
+use crate::history::{HistoryStore, KickContext};
 use crate::recognition::model::WeaponClasses;
 use crate::BotStats;
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
+use std::ops::Deref;
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
 
-pub async fn kick_player(
+pub async fn kick_player<'a>(
     api: &'static BF1Api,
     config: &'static Config,
-    kick_record: Arc<Mutex<PlayerKickHistoryRecord>>,
+    server_config: &'static ServerConfig,
+    kick_record: Arc<Mutex<HistoryStore>>,
     player_name: &String,
     banned_weapon: String,
     category: WeaponClasses,
+    confidence: Option<f32>,
+    evidence_path: Option<String>,
+    detected_at: DateTime<Utc>,
     game_state: Arc<RwLock<GameState>>,
-    server: &ServerDetails,
+    server: &'a ServerDetails,
     bot_stats: Arc<RwLock<BotStats>>,
     is_pending: bool,
 ) {
@@ -30,13 +39,13 @@ pub async fn kick_player(
         return;
     }
 
-    let search_team = |team: &HashMap<String, String>| {
-        if let Some(id) = team.get(player_name) {
-            Some((player_name.clone(), id.clone()))
+    let search_team = |team: &'a HashMap<String, ServerPlayer>| {
+        if let Some(player) = team.get(player_name) {
+            Some((player_name.clone(), player))
         } else {
-            team.iter().find_map(|(name, id)| {
+            team.iter().find_map(|(name, player)| {
                 if config.are_similar(name, player_name, config.player_similar_name_probability) {
-                    Some((name.clone(), id.clone()))
+                    Some((name.clone(), player))
                 } else {
                     None
                 }
@@ -44,12 +53,50 @@ pub async fn kick_player(
         }
     };
 
-    if let Some((player_actual_name, id)) =
+    if let Some((player_actual_name, player)) =
         search_team(&server.team1).or_else(|| search_team(&server.team2))
     {
+        let id = player.player_id;
+        if let Some(immune_reason) = server_config.immunity.reason(server, player) {
+            log_info(
+                format!("Not kicking {player_actual_name} for {banned_weapon}, {immune_reason}")
+                    .as_str(),
+            );
+            let mut game_state = game_state.write().await;
+            game_state
+                .already_kicked_list_players
+                .insert(player_actual_name);
+            game_state.pending_kick_players.remove(player_name);
+            return;
+        }
+
         let game_id = server.game_id.clone();
-        let id_string = String::from(id);
-        let reason = format!("No {banned_weapon}, Read Rules");
+        let context = KickContext {
+            map: server.map.clone(),
+            confidence,
+            evidence_path,
+            detected_at: Some(detected_at),
+        };
+        let escalation = &server_config.escalation;
+        let offences = escalation.count_offences(
+            kick_record.lock().await.deref(),
+            id,
+            config.history_policy.offence_decay,
+        );
+        let step = escalation.step_for(offences);
+        let reason = match &step.reason {
+            Some(template) => server_config.kick_reasons.render_template(
+                template,
+                banned_weapon.as_str(),
+                player_actual_name.as_str(),
+                offences,
+            ),
+            None => server_config.kick_reasons.render(
+                banned_weapon.as_str(),
+                player_actual_name.as_str(),
+                offences,
+            ),
+        };
 
         game_state
             .write()
@@ -57,16 +104,21 @@ pub async fn kick_player(
             .already_kicked_list_players
             .insert(player_actual_name.clone());
 
-        tokio::task::spawn(async {
+        tokio::task::spawn(async move {
             api.kick_player(
                 game_id,
-                id_string,
+                id,
                 player_actual_name,
+                banned_weapon,
                 reason,
                 category,
+                step.action,
+                offences,
+                context,
                 kick_record,
                 bot_stats,
                 config,
+                server_config,
             )
             .await
         });
@@ -79,17 +131,16 @@ pub async fn kick_player(
                 .remove(&player_name.clone());
         }
     } else {
-        let mut game_state = game_state.write().await;
-        if is_pending {
-            game_state.pending_kick_players.insert(
-                player_name.clone(),
-                RecordWeapon {
-                    name: banned_weapon,
-                    category,
-                },
-            );
-        } else {
-            game_state.pending_kick_players.remove(&player_name.clone());
-        }
+        // Not in the roster yet, tried again after every refresh until it expires
+        game_state
+            .write()
+            .await
+            .pending_kick_players
+            .entry(player_name.clone())
+            .or_insert(RecordWeapon {
+                name: banned_weapon,
+                category,
+                detected_at,
+            });
     }
 }
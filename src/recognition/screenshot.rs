@@ -76,7 +76,8 @@ impl Screenshot {
             .collect::<String>()
     }
 
-    pub fn save(&self, filename: &str) -> Result<(), KickbotError> {
+    // Returns the path it was saved to
+    pub fn save(&self, filename: &str) -> Result<String, KickbotError> {
         let safe_name = Self::sanitize_filename(filename);
         let path = format!("screenshots/{}.jpg", safe_name);
         let mut params = Vector::default();
@@ -86,6 +87,6 @@ impl Screenshot {
             return Err(ScreenshotError(format!("Error saving file {}", path)));
         }
         println!("Saved");
-        Ok(())
+        Ok(path)
     }
 }
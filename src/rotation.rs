@@ -0,0 +1,126 @@
+use crate::api::bf1api::server::ServerDetails;
+use crate::api::bf1api::BF1Api;
+use crate::api::errors::{BF1ApiError, BF1ApiSubError};
+use crate::config::ServerConfig;
+use crate::console::log;
+use crate::discord::announce_map_change;
+use std::time::Instant;
+
+pub async fn switch_map(
+    api: &BF1Api,
+    server: &ServerDetails,
+    level_index: usize,
+) -> Result<(), BF1ApiError> {
+    if level_index >= server.rotation.len() {
+        return Err(BF1ApiSubError::ResponseError(format!(
+            "Rotation index {} out of range, rotation has {} maps",
+            level_index,
+            server.rotation.len()
+        ))
+        .into());
+    }
+    if server.guid.is_empty() {
+        return Err(
+            BF1ApiSubError::ResponseError("Server has no persisted game id".to_string()).into(),
+        );
+    }
+
+    api.choose_level(server.guid.clone(), level_index).await
+}
+
+pub struct RotationScheduler {
+    current_map: String,
+    map_start: Instant,
+    seeding_since: Option<Instant>,
+    seeded: bool,
+}
+
+impl Default for RotationScheduler {
+    fn default() -> Self {
+        RotationScheduler {
+            current_map: String::new(),
+            map_start: Instant::now(),
+            seeding_since: None,
+            seeded: false,
+        }
+    }
+}
+
+impl RotationScheduler {
+    // Called after every server details refresh
+    pub async fn check(&mut self, api: &BF1Api, config: &ServerConfig, server: &ServerDetails) {
+        let Some(rotation_control) = &config.rotation_control else {
+            return;
+        };
+
+        let map_key = format!("{}{}", server.map, server.game_mode);
+        if map_key != self.current_map {
+            self.current_map = map_key;
+            self.map_start = Instant::now();
+        }
+
+        let seeding_threshold = rotation_control
+            .seeding_below_players
+            .unwrap_or(config.min_players_for_kick) as usize;
+        // A single low sample is often just a round ending or players reconnecting
+        let below_threshold_for = match server.player_count() >= seeding_threshold {
+            true => {
+                self.seeding_since = None;
+                self.seeded = false;
+                None
+            }
+            false => Some(self.seeding_since.get_or_insert(Instant::now()).elapsed()),
+        };
+
+        let (level_index, reason) = match (rotation_control.seeding_index, server.rotation_index) {
+            (Some(seeding_index), Some(current_index))
+                if !self.seeded
+                    && below_threshold_for
+                        .is_some_and(|elapsed| elapsed >= rotation_control.seeding_duration)
+                    && current_index != seeding_index =>
+            {
+                self.seeded = true;
+                (
+                    seeding_index,
+                    format!(
+                        "Player count stayed below {seeding_threshold} for {} seconds",
+                        rotation_control.seeding_duration.as_secs()
+                    ),
+                )
+            }
+            _ => match (rotation_control.max_map_duration, server.rotation_index) {
+                (Some(max_map_duration), Some(current_index))
+                    if self.map_start.elapsed() >= max_map_duration =>
+                {
+                    (
+                        (current_index + 1) % server.rotation.len(),
+                        format!(
+                            "Map has been running for over {} minutes",
+                            max_map_duration.as_secs() / 60
+                        ),
+                    )
+                }
+                _ => return,
+            },
+        };
+
+        // Don't try again every refresh if the switch fails
+        self.map_start = Instant::now();
+
+        match switch_map(api, server, level_index).await {
+            Ok(_) => {
+                if let Err(err) = announce_map_change(
+                    &config.monitoring_webhook,
+                    server,
+                    level_index,
+                    reason.as_str(),
+                )
+                .await
+                {
+                    log(&err);
+                }
+            }
+            Err(err) => log(&err),
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use crate::errors::KickbotError;
+use crate::history::{HistoryStore, KickAction, KickEntry};
+use chrono::{DateTime, Datelike, Local, TimeDelta, Timelike, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+pub const WEEKLY_SUMMARY_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+const WEEKLY_SUMMARY_PERIOD: TimeDelta = TimeDelta::days(7);
+// Kept in the history store so restarts don't resend or skip a week
+const LAST_WEEKLY_SUMMARY_KEY: &str = "last_weekly_summary";
+
+pub const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+// How many entries of each breakdown make it into the text report and the embed
+const TOP_COUNT: usize = 5;
+
+#[derive(Debug, Serialize)]
+pub struct LatencySummary {
+    pub samples: u64,
+    pub mean_secs: i64,
+    pub median_secs: i64,
+    pub p95_secs: i64,
+    pub max_secs: i64,
+}
+
+impl LatencySummary {
+    fn from_latencies(mut latencies: Vec<TimeDelta>) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort();
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100].num_seconds();
+        let total: i64 = latencies.iter().map(TimeDelta::num_seconds).sum();
+        Some(LatencySummary {
+            samples: latencies.len() as u64,
+            mean_secs: total / latencies.len() as i64,
+            median_secs: percentile(50),
+            p95_secs: percentile(95),
+            max_secs: latencies.last().unwrap().num_seconds(),
+        })
+    }
+}
+
+// Kicks over a period broken down a few ways. Pardoned kicks are counted as false positives
+// and left out of the breakdowns, unconfirmed kicks are kicks the player stayed through.
+// Warnings are only counted, they aren't kicks
+#[derive(Debug, Serialize)]
+pub struct EnforcementReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub kicks: u64,
+    pub pardoned: u64,
+    // Included in kicks
+    pub unconfirmed: u64,
+    pub warnings: u64,
+    pub false_positive_rate: f64,
+    pub by_rule: BTreeMap<String, u64>,
+    pub by_map: BTreeMap<String, u64>,
+    pub by_server: BTreeMap<String, u64>,
+    // Local time, index 0 is midnight
+    pub by_hour: [u64; 24],
+    // Local time, index 0 is Monday
+    pub by_weekday: [u64; 7],
+    pub latency: Option<LatencySummary>,
+}
+
+impl EnforcementReport {
+    // entries should already be limited to from..to
+    pub fn build(entries: &[KickEntry], from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+        let mut report = EnforcementReport {
+            from,
+            to,
+            kicks: 0,
+            pardoned: 0,
+            unconfirmed: 0,
+            warnings: 0,
+            false_positive_rate: 0.0,
+            by_rule: BTreeMap::new(),
+            by_map: BTreeMap::new(),
+            by_server: BTreeMap::new(),
+            by_hour: [0; 24],
+            by_weekday: [0; 7],
+            latency: None,
+        };
+
+        let mut latencies = vec![];
+        for entry in entries {
+            if entry.action == KickAction::Warn {
+                report.warnings += 1;
+                continue;
+            }
+            if entry.pardoned_at.is_some() {
+                report.pardoned += 1;
+                continue;
+            }
+            report.kicks += 1;
+            if entry.confirmed == Some(false) {
+                report.unconfirmed += 1;
+            }
+            *report.by_rule.entry(entry.rule.clone()).or_default() += 1;
+            *report.by_map.entry(or_unknown(&entry.map)).or_default() += 1;
+            *report
+                .by_server
+                .entry(or_unknown(&entry.server))
+                .or_default() += 1;
+
+            let kicked_at = entry.kicked_at.with_timezone(&Local);
+            report.by_hour[kicked_at.hour() as usize] += 1;
+            report.by_weekday[kicked_at.weekday().num_days_from_monday() as usize] += 1;
+
+            if let Some(latency) = entry.latency() {
+                latencies.push(latency);
+            }
+        }
+
+        let total = report.kicks + report.pardoned;
+        if total > 0 {
+            report.false_positive_rate = report.pardoned as f64 / total as f64;
+        }
+        report.latency = LatencySummary::from_latencies(latencies);
+        report
+    }
+
+    pub fn busiest_hour(&self) -> Option<usize> {
+        busiest(&self.by_hour)
+    }
+
+    pub fn busiest_weekday(&self) -> Option<&'static str> {
+        busiest(&self.by_weekday).map(|day| WEEKDAYS[day])
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!(
+                "Kicks from {} to {}",
+                self.from.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                self.to.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+            ),
+            format!(
+                "{} kicks, {} pardoned ({:.1}% false positives), {} not confirmed, {} warnings",
+                self.kicks,
+                self.pardoned,
+                self.false_positive_rate * 100.0,
+                self.unconfirmed,
+                self.warnings
+            ),
+        ];
+
+        for (title, counts) in [
+            ("By rule", &self.by_rule),
+            ("By map", &self.by_map),
+            ("By server", &self.by_server),
+        ] {
+            lines.push(format!("{title}:"));
+            for (name, count) in top(counts, TOP_COUNT) {
+                lines.push(format!("  {name}: {count}"));
+            }
+        }
+
+        lines.push("By hour:".to_string());
+        for (hour, count) in self.by_hour.iter().enumerate() {
+            if *count > 0 {
+                lines.push(format!("  {hour:02}:00 {count}"));
+            }
+        }
+        lines.push("By weekday:".to_string());
+        for (day, count) in self.by_weekday.iter().enumerate() {
+            lines.push(format!("  {} {count}", WEEKDAYS[day]));
+        }
+
+        lines.push(match &self.latency {
+            Some(latency) => format!(
+                "Detection to kick: median {}s, p95 {}s, max {}s over {} kicks",
+                latency.median_secs, latency.p95_secs, latency.max_secs, latency.samples
+            ),
+            None => "Detection to kick: no kicks with a detection time".to_string(),
+        });
+        lines.join("\n")
+    }
+}
+
+// The report since the last summary once a week has passed. The first check only starts the clock
+pub fn due_weekly_report(
+    kick_record: &HistoryStore,
+) -> Result<Option<EnforcementReport>, KickbotError> {
+    let now = Utc::now();
+    let last_summary = kick_record
+        .get_meta(LAST_WEEKLY_SUMMARY_KEY)?
+        .and_then(|timestamp| timestamp.parse::<i64>().ok())
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0));
+    let Some(last_summary) = last_summary else {
+        mark_weekly_summary_sent(kick_record, now)?;
+        return Ok(None);
+    };
+    if now - last_summary < WEEKLY_SUMMARY_PERIOD {
+        return Ok(None);
+    }
+
+    let entries = kick_record.kicks_between(last_summary, now)?;
+    Ok(Some(EnforcementReport::build(&entries, last_summary, now)))
+}
+
+pub fn mark_weekly_summary_sent(
+    kick_record: &HistoryStore,
+    sent_at: DateTime<Utc>,
+) -> Result<(), KickbotError> {
+    kick_record.set_meta(
+        LAST_WEEKLY_SUMMARY_KEY,
+        sent_at.timestamp().to_string().as_str(),
+    )
+}
+
+fn or_unknown(value: &str) -> String {
+    match value.is_empty() {
+        true => "Unknown".to_string(),
+        false => value.to_string(),
+    }
+}
+
+fn busiest(counts: &[u64]) -> Option<usize> {
+    counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .max_by_key(|(_, count)| **count)
+        .map(|(index, _)| index)
+}
+
+// Highest count first, ties by name
+pub fn top(counts: &BTreeMap<String, u64>, limit: usize) -> Vec<(&str, u64)> {
+    let mut sorted: Vec<(&str, u64)> = counts
+        .iter()
+        .map(|(name, count)| (name.as_str(), *count))
+        .collect();
+    sorted.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    sorted.truncate(limit);
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{KickAction, KickContext};
+
+    fn kick(rule: &str, map: &str, kicked_at: DateTime<Utc>, latency_secs: i64) -> KickEntry {
+        let context = KickContext {
+            map: map.to_string(),
+            detected_at: Some(kicked_at - TimeDelta::seconds(latency_secs)),
+            ..KickContext::default()
+        };
+        let mut entry = KickEntry::new(1, "Player", rule, "", "![VG]", "123", &context);
+        entry.kicked_at = kicked_at;
+        entry
+    }
+
+    #[test]
+    fn build_breaks_kicks_down() {
+        let to = Utc::now();
+        let from = to - TimeDelta::days(7);
+        let kicked_at = to - TimeDelta::hours(1);
+
+        let mut pardoned = kick("SMG", "Amiens", kicked_at, 5);
+        pardoned.pardoned_at = Some(to);
+        let mut unconfirmed = kick("SMG", "", kicked_at, 30);
+        unconfirmed.confirmed = Some(false);
+        let mut warning = kick("Shotgun", "Amiens", kicked_at, 1);
+        warning.action = KickAction::Warn;
+        let entries = vec![
+            kick("SMG", "Amiens", kicked_at, 10),
+            kick("Shotgun", "Amiens", kicked_at, 20),
+            unconfirmed,
+            pardoned,
+            warning,
+        ];
+
+        let report = EnforcementReport::build(&entries, from, to);
+        assert_eq!(report.kicks, 3);
+        assert_eq!(report.pardoned, 1);
+        assert_eq!(report.unconfirmed, 1);
+        assert_eq!(report.warnings, 1);
+        assert_eq!(report.false_positive_rate, 0.25);
+        assert_eq!(report.by_rule["SMG"], 2);
+        assert_eq!(report.by_rule["Shotgun"], 1);
+        assert_eq!(report.by_map["Amiens"], 2);
+        assert_eq!(report.by_map["Unknown"], 1);
+        assert_eq!(report.by_server["![VG]"], 3);
+
+        let local = kicked_at.with_timezone(&Local);
+        assert_eq!(report.busiest_hour(), Some(local.hour() as usize));
+        assert_eq!(
+            report.busiest_weekday(),
+            Some(WEEKDAYS[local.weekday().num_days_from_monday() as usize])
+        );
+
+        let latency = report.latency.unwrap();
+        assert_eq!(latency.samples, 3);
+        assert_eq!(latency.median_secs, 20);
+        assert_eq!(latency.max_secs, 30);
+    }
+
+    #[test]
+    fn build_without_kicks() {
+        let to = Utc::now();
+        let report = EnforcementReport::build(&[], to - TimeDelta::days(7), to);
+        assert_eq!(report.kicks, 0);
+        assert_eq!(report.false_positive_rate, 0.0);
+        assert!(report.latency.is_none());
+        assert_eq!(report.busiest_hour(), None);
+    }
+
+    #[test]
+    fn top_sorts_by_count_then_name() {
+        let counts = BTreeMap::from([
+            ("b".to_string(), 2),
+            ("a".to_string(), 2),
+            ("c".to_string(), 5),
+        ]);
+        assert_eq!(top(&counts, 2), vec![("c", 5), ("a", 2)]);
+    }
+}